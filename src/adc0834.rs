@@ -0,0 +1,320 @@
+use std::error::Error;
+use std::fmt;
+
+use rppal::gpio::{Gpio, InputPin, OutputPin};
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+
+use crate::analog::{AnalogInput, NoSuchChannel};
+use crate::timing;
+
+// The ADC0834 is rated up to a 400 kHz clock.
+const SPI_CLOCK: u32 = 400_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Ch0,
+    Ch1,
+    Ch2,
+    Ch3,
+    // Differential inputs: the first channel named is +, the second -.
+    Diff01,
+    Diff10,
+    Diff23,
+    Diff32,
+}
+
+impl Channel {
+    // (SGL, ODD, Select)
+    fn mux_bits(&self) -> (u8, u8, u8) {
+        match self {
+            Channel::Ch0 => (1, 0, 0),
+            Channel::Ch1 => (1, 1, 0),
+            Channel::Ch2 => (1, 0, 1),
+            Channel::Ch3 => (1, 1, 1),
+            Channel::Diff01 => (0, 0, 0),
+            Channel::Diff10 => (0, 1, 0),
+            Channel::Diff23 => (0, 0, 1),
+            Channel::Diff32 => (0, 1, 1),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Adc0834Error {
+    GpioError(rppal::gpio::Error),
+    SpiError(rppal::spi::Error),
+    // The MSB-first and LSB-first data did not match.
+    Mismatch { msb: u8, lsb: u8 },
+}
+
+impl fmt::Display for Adc0834Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Adc0834Error::GpioError(e) => write!(f, "gpio error: {}", e),
+            Adc0834Error::SpiError(e) => write!(f, "spi error: {}", e),
+            Adc0834Error::Mismatch { msb, lsb } => {
+                write!(
+                    f,
+                    "msb-first {:#04x} and lsb-first {:#04x} differ",
+                    msb, lsb
+                )
+            }
+        }
+    }
+}
+
+impl Error for Adc0834Error {}
+
+impl From<rppal::gpio::Error> for Adc0834Error {
+    fn from(e: rppal::gpio::Error) -> Adc0834Error {
+        Adc0834Error::GpioError(e)
+    }
+}
+
+impl From<rppal::spi::Error> for Adc0834Error {
+    fn from(e: rppal::spi::Error) -> Adc0834Error {
+        Adc0834Error::SpiError(e)
+    }
+}
+
+fn snd_bit(clk_pin: &mut OutputPin, input_pin: &mut OutputPin, value: u8) {
+    clk_pin.set_low();
+    timing::delay_us(2);
+    if value == 0 {
+        input_pin.set_low();
+    } else {
+        input_pin.set_high();
+    }
+    clk_pin.set_high();
+    timing::delay_us(2);
+}
+
+fn rcv_bit(clk_pin: &mut OutputPin, output_pin: &mut InputPin) -> u8 {
+    clk_pin.set_low();
+    timing::delay_us(2);
+    let result = if output_pin.is_high() { 1 } else { 0 };
+    clk_pin.set_high();
+    timing::delay_us(2);
+    result
+}
+
+struct BitBangPins {
+    adc_cs: OutputPin,
+    adc_do: InputPin,
+    adc_di: OutputPin,
+    adc_clk: OutputPin,
+}
+
+enum Transport {
+    BitBang(Box<BitBangPins>),
+    Spi(Spi),
+}
+
+pub struct Adc0834 {
+    transport: Transport,
+    retries: u32,
+    vref: f64,
+}
+
+impl Adc0834 {
+    pub fn new(adc_cs: u8, adc_do: u8, adc_di: u8, adc_clk: u8) -> Result<Self, Adc0834Error> {
+        let gpio = Gpio::new()?;
+        Ok(Self {
+            transport: Transport::BitBang(Box::new(BitBangPins {
+                adc_cs: gpio.get(adc_cs)?.into_output_high(),
+                adc_do: gpio.get(adc_do)?.into_input(),
+                adc_di: gpio.get(adc_di)?.into_output(),
+                adc_clk: gpio.get(adc_clk)?.into_output(),
+            })),
+            retries: 3,
+            vref: 5.0,
+        })
+    }
+
+    // CS to CE0/CE1, CLK to SCLK, DI to MOSI, DO to MISO.
+    pub fn with_spi(slave_select: SlaveSelect) -> Result<Self, Adc0834Error> {
+        let spi = Spi::new(Bus::Spi0, slave_select, SPI_CLOCK, Mode::Mode0)?;
+        Ok(Self {
+            transport: Transport::Spi(spi),
+            retries: 3,
+            vref: 5.0,
+        })
+    }
+
+    // How many times to retry when the MSB-first and LSB-first data disagree.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    // The voltage on VREF; 5 V on the kit, the same as VCC.
+    pub fn with_reference(mut self, vref: f64) -> Self {
+        self.vref = vref;
+        self
+    }
+
+    pub fn get_adc_result(&mut self, channel: Channel) -> Result<u8, Adc0834Error> {
+        let mut attempt = 0;
+        loop {
+            let (msb, lsb) = match &mut self.transport {
+                Transport::BitBang(pins) => bit_bang_conversion(pins, channel),
+                Transport::Spi(spi) => spi_conversion(spi, channel)?,
+            };
+            if msb == lsb {
+                return Ok(msb);
+            }
+            if attempt >= self.retries {
+                return Err(Adc0834Error::Mismatch { msb, lsb });
+            }
+            attempt += 1;
+        }
+    }
+}
+
+impl AnalogInput for Adc0834 {
+    fn channels(&self) -> u8 {
+        4
+    }
+
+    fn resolution_bits(&self) -> u8 {
+        8
+    }
+
+    fn reference_voltage(&self) -> f64 {
+        self.vref
+    }
+
+    fn read_raw(&mut self, channel: u8) -> Result<u16, Box<dyn Error>> {
+        let channel = match channel {
+            0 => Channel::Ch0,
+            1 => Channel::Ch1,
+            2 => Channel::Ch2,
+            3 => Channel::Ch3,
+            _ => return Err(Box::new(NoSuchChannel(channel))),
+        };
+        Ok(self.get_adc_result(channel)? as u16)
+    }
+}
+
+// One conversion takes 24 clocks. The first four bits are zeros, which the chip
+// skips, so the start bit lands on clock 5.
+//
+//   clock     1-4   5      6    7    8       9      10-17     18-24
+//   DI        0     start  SGL  ODD  Select  -      -         -
+//   DO        -     -      -    -    -       -      MSB-First LSB-First (D1-D7)
+fn spi_conversion(spi: &Spi, channel: Channel) -> Result<(u8, u8), rppal::spi::Error> {
+    let mut read = [0u8; 3];
+    spi.transfer(&mut read, &spi_command(channel))?;
+    Ok(spi_decode(read))
+}
+
+// The three bytes sent on DI for a conversion of `channel`.
+fn spi_command(channel: Channel) -> [u8; 3] {
+    let (sgl, odd, select) = channel.mux_bits();
+    [0b1000 | sgl << 2 | odd << 1 | select, 0, 0]
+}
+
+// The MSB-first and LSB-first results from the three bytes read on DO. The LSB-first
+// half shares D0 with the MSB-first one, so it is returned with D0 filled in.
+fn spi_decode(read: [u8; 3]) -> (u8, u8) {
+    let bits = u32::from_be_bytes([0, read[0], read[1], read[2]]);
+    let msb = (bits >> 7) as u8;
+    let lsb = (bits & 0x7f) as u8;
+    (msb, lsb.reverse_bits() | (msb & 1))
+}
+
+fn bit_bang_conversion(pins: &mut BitBangPins, channel: Channel) -> (u8, u8) {
+    let (sgl, odd, select) = channel.mux_bits();
+    let BitBangPins {
+        adc_cs,
+        adc_do,
+        adc_di,
+        adc_clk,
+    } = pins;
+
+    // Start of the conversion
+    adc_cs.set_low();
+    // Start bit
+    snd_bit(adc_clk, adc_di, 1);
+    // SGL
+    snd_bit(adc_clk, adc_di, sgl);
+    // ODD
+    snd_bit(adc_clk, adc_di, odd);
+    // Select
+    snd_bit(adc_clk, adc_di, select);
+
+    // A clock the chip ignores; the value sent does not matter.
+    snd_bit(adc_clk, adc_di, 1);
+
+    let mut msb = 0;
+    // MSB-First Data
+    for _ in 0..7 {
+        msb = msb << 1 | rcv_bit(adc_clk, adc_do);
+    }
+    // The bit MSB-first and LSB-first data share, D0
+    let d0 = rcv_bit(adc_clk, adc_do);
+    msb = msb << 1 | d0;
+    let mut lsb = d0;
+    // LSB-First Data
+    for i in 1..8 {
+        lsb |= rcv_bit(adc_clk, adc_do) << i;
+    }
+    // End of the conversion
+    adc_cs.set_high();
+    (msb, lsb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the chip sends for `value`: D7-D0 on clocks 10-17, then D1-D7 on 18-24.
+    fn frame(value: u8) -> [u8; 3] {
+        let mut bits = (value as u32) << 7;
+        for i in 1..8 {
+            bits |= ((value >> i) as u32 & 1) << (7 - i);
+        }
+        let [_, a, b, c] = bits.to_be_bytes();
+        [a, b, c]
+    }
+
+    #[test]
+    fn command_bits() {
+        assert_eq!(spi_command(Channel::Ch0), [0b1100, 0, 0]);
+        assert_eq!(spi_command(Channel::Ch1), [0b1110, 0, 0]);
+        assert_eq!(spi_command(Channel::Ch2), [0b1101, 0, 0]);
+        assert_eq!(spi_command(Channel::Ch3), [0b1111, 0, 0]);
+        assert_eq!(spi_command(Channel::Diff01), [0b1000, 0, 0]);
+        assert_eq!(spi_command(Channel::Diff10), [0b1010, 0, 0]);
+        assert_eq!(spi_command(Channel::Diff23), [0b1001, 0, 0]);
+        assert_eq!(spi_command(Channel::Diff32), [0b1011, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_both_halves() {
+        for value in [0x00, 0x01, 0x80, 0xb2, 0xff] {
+            assert_eq!(spi_decode(frame(value)), (value, value), "{:#04x}", value);
+        }
+        // 0xb2: 1011 0010, then 1001 101 for D1-D7.
+        assert_eq!(frame(0xb2), [0x00, 0x59, 0x4d]);
+    }
+
+    #[test]
+    fn ignores_do_before_the_data() {
+        // DO floats during the first nine clocks.
+        let mut read = frame(0x5a);
+        read[0] |= 0xff;
+        read[1] |= 0x80;
+        assert_eq!(spi_decode(read), (0x5a, 0x5a));
+    }
+
+    #[test]
+    fn corrupted_bit_shows_as_a_mismatch() {
+        let mut read = frame(0x5a);
+        // D7 of the LSB-first half.
+        read[2] ^= 0x01;
+        let (msb, lsb) = spi_decode(read);
+        assert_eq!(msb, 0x5a);
+        assert_ne!(msb, lsb);
+    }
+}
@@ -0,0 +1,600 @@
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::analog::{AnalogInput, NoSuchChannel};
+
+// ADDR to GND. 0x49 with ADDR to VDD, 0x4a to SDA, 0x4b to SCL.
+pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+const REG_LO_THRESH: u8 = 0x02;
+const REG_HI_THRESH: u8 = 0x03;
+
+const OS_START: u16 = 1 << 15;
+const MODE_SINGLE_SHOT: u16 = 1 << 8;
+const COMP_QUE_DISABLE: u16 = 0b11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Ain0,
+    Ain1,
+    Ain2,
+    Ain3,
+    // Differential, positive input first.
+    Ain0Ain1,
+    Ain0Ain3,
+    Ain1Ain3,
+    Ain2Ain3,
+}
+
+impl Input {
+    fn mux(&self) -> u16 {
+        let bits = match self {
+            Input::Ain0Ain1 => 0b000,
+            Input::Ain0Ain3 => 0b001,
+            Input::Ain1Ain3 => 0b010,
+            Input::Ain2Ain3 => 0b011,
+            Input::Ain0 => 0b100,
+            Input::Ain1 => 0b101,
+            Input::Ain2 => 0b110,
+            Input::Ain3 => 0b111,
+        };
+        bits << 12
+    }
+}
+
+// Full-scale range of the programmable gain amplifier. Inputs must still stay
+// within GND-0.3 V and VDD+0.3 V whatever the range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gain {
+    Fsr6_144,
+    Fsr4_096,
+    Fsr2_048,
+    Fsr1_024,
+    Fsr0_512,
+    Fsr0_256,
+}
+
+impl Gain {
+    pub fn full_scale(&self) -> f64 {
+        match self {
+            Gain::Fsr6_144 => 6.144,
+            Gain::Fsr4_096 => 4.096,
+            Gain::Fsr2_048 => 2.048,
+            Gain::Fsr1_024 => 1.024,
+            Gain::Fsr0_512 => 0.512,
+            Gain::Fsr0_256 => 0.256,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        let bits = match self {
+            Gain::Fsr6_144 => 0b000,
+            Gain::Fsr4_096 => 0b001,
+            Gain::Fsr2_048 => 0b010,
+            Gain::Fsr1_024 => 0b011,
+            Gain::Fsr0_512 => 0b100,
+            Gain::Fsr0_256 => 0b101,
+        };
+        bits << 9
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataRate {
+    Sps8,
+    Sps16,
+    Sps32,
+    Sps64,
+    Sps128,
+    Sps250,
+    Sps475,
+    Sps860,
+}
+
+impl DataRate {
+    pub fn samples_per_second(&self) -> u32 {
+        match self {
+            DataRate::Sps8 => 8,
+            DataRate::Sps16 => 16,
+            DataRate::Sps32 => 32,
+            DataRate::Sps64 => 64,
+            DataRate::Sps128 => 128,
+            DataRate::Sps250 => 250,
+            DataRate::Sps475 => 475,
+            DataRate::Sps860 => 860,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        let bits = match self {
+            DataRate::Sps8 => 0b000,
+            DataRate::Sps16 => 0b001,
+            DataRate::Sps32 => 0b010,
+            DataRate::Sps64 => 0b011,
+            DataRate::Sps128 => 0b100,
+            DataRate::Sps250 => 0b101,
+            DataRate::Sps475 => 0b110,
+            DataRate::Sps860 => 0b111,
+        };
+        bits << 5
+    }
+
+    fn conversion_time(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.samples_per_second() as f64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparatorMode {
+    // ALERT asserts above `high` and releases below `low`.
+    Traditional,
+    // ALERT asserts outside `low..=high`.
+    Window,
+}
+
+// How many conversions in a row must cross a threshold before ALERT asserts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparatorQueue {
+    One,
+    Two,
+    Four,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparator {
+    pub mode: ComparatorMode,
+    pub active_high: bool,
+    // Keep ALERT asserted until the conversion register is read.
+    pub latching: bool,
+    pub queue: ComparatorQueue,
+    // Thresholds in raw counts.
+    pub low: i16,
+    pub high: i16,
+}
+
+impl Comparator {
+    // Turns ALERT/RDY into a conversion-ready output that pulses after every
+    // conversion in continuous mode.
+    pub fn conversion_ready() -> Self {
+        Comparator {
+            mode: ComparatorMode::Traditional,
+            active_high: false,
+            latching: false,
+            queue: ComparatorQueue::One,
+            low: 0,
+            high: -1,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        let mut bits = match self.queue {
+            ComparatorQueue::One => 0b00,
+            ComparatorQueue::Two => 0b01,
+            ComparatorQueue::Four => 0b10,
+        };
+        if self.mode == ComparatorMode::Window {
+            bits |= 1 << 4;
+        }
+        if self.active_high {
+            bits |= 1 << 3;
+        }
+        if self.latching {
+            bits |= 1 << 2;
+        }
+        bits
+    }
+}
+
+#[derive(Debug)]
+pub enum Ads1115Error<E> {
+    I2cError(E),
+    // The conversion did not finish in time.
+    TimeOut,
+}
+
+impl<E: fmt::Debug> fmt::Display for Ads1115Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ads1115Error::I2cError(e) => write!(f, "i2c error: {:?}", e),
+            Ads1115Error::TimeOut => write!(f, "timed out waiting for the conversion"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> Error for Ads1115Error<E> {}
+
+// Works with any blocking I2C bus, e.g. `rppal::i2c::I2c`.
+pub struct Ads1115<I2C> {
+    i2c: I2C,
+    address: u8,
+    gain: Gain,
+    data_rate: DataRate,
+    comparator: Option<Comparator>,
+    continuous: Option<Input>,
+}
+
+impl<I2C, E> Ads1115<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Ads1115 {
+            i2c,
+            address,
+            gain: Gain::Fsr2_048,
+            data_rate: DataRate::Sps128,
+            comparator: None,
+            continuous: None,
+        }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    // Settings take effect with the next conversion, or right away in continuous mode.
+    pub fn set_gain(&mut self, gain: Gain) -> Result<(), Ads1115Error<E>> {
+        self.gain = gain;
+        self.rewrite_continuous()
+    }
+
+    pub fn set_data_rate(&mut self, data_rate: DataRate) -> Result<(), Ads1115Error<E>> {
+        self.data_rate = data_rate;
+        self.rewrite_continuous()
+    }
+
+    pub fn set_comparator(
+        &mut self,
+        comparator: Option<Comparator>,
+    ) -> Result<(), Ads1115Error<E>> {
+        if let Some(c) = &comparator {
+            self.write_register(REG_LO_THRESH, c.low as u16)?;
+            self.write_register(REG_HI_THRESH, c.high as u16)?;
+        }
+        self.comparator = comparator;
+        self.rewrite_continuous()
+    }
+
+    // Raw counts to volts for the current gain.
+    pub fn to_voltage(&self, raw: i16) -> f64 {
+        raw as f64 * self.gain.full_scale() / 32768.0
+    }
+
+    pub fn read_single(&mut self, input: Input) -> Result<i16, Ads1115Error<E>> {
+        let config = self.config(input) | OS_START | MODE_SINGLE_SHOT;
+        self.write_register(REG_CONFIG, config)?;
+        self.continuous = None;
+
+        thread::sleep(self.data_rate.conversion_time());
+        let deadline = Instant::now() + self.data_rate.conversion_time() * 2;
+        // OS reads back as 1 once the device is idle again.
+        while self.read_register(REG_CONFIG)? & OS_START == 0 {
+            if Instant::now() > deadline {
+                return Err(Ads1115Error::TimeOut);
+            }
+            thread::sleep(Duration::from_micros(100));
+        }
+        Ok(self.read_register(REG_CONVERSION)? as i16)
+    }
+
+    pub fn start_continuous(&mut self, input: Input) -> Result<(), Ads1115Error<E>> {
+        self.write_register(REG_CONFIG, self.config(input))?;
+        self.continuous = Some(input);
+        Ok(())
+    }
+
+    // The latest result of continuous conversion.
+    pub fn read_continuous(&mut self) -> Result<i16, Ads1115Error<E>> {
+        Ok(self.read_register(REG_CONVERSION)? as i16)
+    }
+
+    // Back to single-shot mode, where the device powers down between conversions.
+    pub fn stop_continuous(&mut self) -> Result<(), Ads1115Error<E>> {
+        if let Some(input) = self.continuous.take() {
+            self.write_register(REG_CONFIG, self.config(input) | MODE_SINGLE_SHOT)?;
+        }
+        Ok(())
+    }
+
+    fn config(&self, input: Input) -> u16 {
+        let comparator = self
+            .comparator
+            .as_ref()
+            .map_or(COMP_QUE_DISABLE, |c| c.bits());
+        input.mux() | self.gain.bits() | self.data_rate.bits() | comparator
+    }
+
+    fn rewrite_continuous(&mut self) -> Result<(), Ads1115Error<E>> {
+        match self.continuous {
+            Some(input) => self.start_continuous(input),
+            None => Ok(()),
+        }
+    }
+
+    fn write_register(&mut self, register: u8, value: u16) -> Result<(), Ads1115Error<E>> {
+        let [hi, lo] = value.to_be_bytes();
+        self.i2c
+            .write(self.address, &[register, hi, lo])
+            .map_err(Ads1115Error::I2cError)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u16, Ads1115Error<E>> {
+        let mut buf = [0u8; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buf)
+            .map_err(Ads1115Error::I2cError)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
+
+// Single-ended inputs only reach the positive half of the range, so they are read as
+// 15-bit values against the full-scale voltage of the current gain.
+impl<I2C, E> AnalogInput for Ads1115<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: fmt::Debug + 'static,
+{
+    fn channels(&self) -> u8 {
+        4
+    }
+
+    fn resolution_bits(&self) -> u8 {
+        15
+    }
+
+    fn reference_voltage(&self) -> f64 {
+        self.gain.full_scale()
+    }
+
+    fn read_raw(&mut self, channel: u8) -> Result<u16, Box<dyn Error>> {
+        let input = match channel {
+            0 => Input::Ain0,
+            1 => Input::Ain1,
+            2 => Input::Ain2,
+            3 => Input::Ain3,
+            _ => return Err(Box::new(NoSuchChannel(channel))),
+        };
+        Ok(self.read_single(input)?.max(0) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    // Records register writes and answers reads from a script. The config register
+    // reads back from `config_reads` in turn, then `idle_config`.
+    struct FakeBus {
+        writes: Vec<(u8, u8, u16)>,
+        config_reads: VecDeque<u16>,
+        idle_config: u16,
+        config_polls: usize,
+        conversion: u16,
+    }
+
+    impl FakeBus {
+        fn new() -> Self {
+            FakeBus {
+                writes: vec![],
+                config_reads: VecDeque::new(),
+                idle_config: OS_START,
+                config_polls: 0,
+                conversion: 0,
+            }
+        }
+
+        fn last_write(&self, register: u8) -> Option<u16> {
+            self.writes
+                .iter()
+                .rev()
+                .find(|(_, reg, _)| *reg == register)
+                .map(|(_, _, value)| *value)
+        }
+    }
+
+    impl Write for FakeBus {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            match bytes {
+                [register, hi, lo] => {
+                    self.writes
+                        .push((address, *register, u16::from_be_bytes([*hi, *lo])));
+                    Ok(())
+                }
+                _ => Err(()),
+            }
+        }
+    }
+
+    impl WriteRead for FakeBus {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            let value = match bytes {
+                [REG_CONFIG] => {
+                    self.config_polls += 1;
+                    self.config_reads.pop_front().unwrap_or(self.idle_config)
+                }
+                [REG_CONVERSION] => self.conversion,
+                _ => return Err(()),
+            };
+            buffer.copy_from_slice(&value.to_be_bytes());
+            Ok(())
+        }
+    }
+
+    fn adc() -> Ads1115<FakeBus> {
+        Ads1115::new(FakeBus::new(), DEFAULT_ADDRESS)
+    }
+
+    // The config word written for a single-shot read of `input`.
+    fn single_shot_config(adc: &mut Ads1115<FakeBus>, input: Input) -> u16 {
+        adc.read_single(input).unwrap();
+        adc.i2c.last_write(REG_CONFIG).unwrap()
+    }
+
+    #[test]
+    fn default_single_shot_config() {
+        let mut adc = adc();
+        // AIN0 single-ended, ±2.048 V, single-shot, 128 SPS, comparator off.
+        assert_eq!(single_shot_config(&mut adc, Input::Ain0), 0xc583);
+        assert!(adc
+            .i2c
+            .writes
+            .iter()
+            .all(|(address, _, _)| *address == DEFAULT_ADDRESS));
+    }
+
+    #[test]
+    fn mux_bits() {
+        let inputs = [
+            (Input::Ain0Ain1, 0b000),
+            (Input::Ain0Ain3, 0b001),
+            (Input::Ain1Ain3, 0b010),
+            (Input::Ain2Ain3, 0b011),
+            (Input::Ain0, 0b100),
+            (Input::Ain1, 0b101),
+            (Input::Ain2, 0b110),
+            (Input::Ain3, 0b111),
+        ];
+        let mut adc = adc();
+        for (input, bits) in inputs {
+            let config = single_shot_config(&mut adc, input);
+            assert_eq!(config >> 12 & 0b111, bits, "{:?}", input);
+            assert_eq!(config & !(0b111 << 12), 0x8583, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn gain_bits() {
+        let gains = [
+            (Gain::Fsr6_144, 0b000),
+            (Gain::Fsr4_096, 0b001),
+            (Gain::Fsr2_048, 0b010),
+            (Gain::Fsr1_024, 0b011),
+            (Gain::Fsr0_512, 0b100),
+            (Gain::Fsr0_256, 0b101),
+        ];
+        let mut adc = adc();
+        for (gain, bits) in gains {
+            adc.set_gain(gain).unwrap();
+            let config = single_shot_config(&mut adc, Input::Ain0);
+            assert_eq!(config >> 9 & 0b111, bits, "{:?}", gain);
+        }
+    }
+
+    #[test]
+    fn data_rate_bits() {
+        let rates = [
+            (DataRate::Sps8, 0b000),
+            (DataRate::Sps16, 0b001),
+            (DataRate::Sps32, 0b010),
+            (DataRate::Sps64, 0b011),
+            (DataRate::Sps128, 0b100),
+            (DataRate::Sps250, 0b101),
+            (DataRate::Sps475, 0b110),
+            (DataRate::Sps860, 0b111),
+        ];
+        let mut adc = adc();
+        for (rate, bits) in rates {
+            adc.set_data_rate(rate).unwrap();
+            let config = single_shot_config(&mut adc, Input::Ain0);
+            assert_eq!(config >> 5 & 0b111, bits, "{:?}", rate);
+        }
+    }
+
+    #[test]
+    fn single_shot_polls_until_the_conversion_is_done() {
+        let mut adc = adc();
+        adc.set_data_rate(DataRate::Sps860).unwrap();
+        adc.i2c.config_reads = VecDeque::from([0x0583, 0x0583, 0x8583]);
+        adc.i2c.conversion = 0x1234;
+        assert_eq!(adc.read_single(Input::Ain0).unwrap(), 0x1234);
+        assert_eq!(adc.i2c.config_polls, 3);
+    }
+
+    #[test]
+    fn single_shot_times_out() {
+        let mut adc = adc();
+        adc.set_data_rate(DataRate::Sps860).unwrap();
+        adc.i2c.idle_config = 0x0583;
+        assert!(matches!(
+            adc.read_single(Input::Ain0),
+            Err(Ads1115Error::TimeOut)
+        ));
+    }
+
+    #[test]
+    fn raw_to_volts() {
+        let mut adc = adc();
+        assert_eq!(adc.to_voltage(16384), 1.024);
+        assert_eq!(adc.to_voltage(-32768), -2.048);
+        adc.set_gain(Gain::Fsr6_144).unwrap();
+        assert_eq!(adc.to_voltage(32767), 32767.0 * 6.144 / 32768.0);
+
+        // Single-ended reads clamp the small negative offset to 0.
+        adc.i2c.conversion = 0xffff;
+        assert_eq!(adc.read_raw(0).unwrap(), 0);
+        // Against the 15-bit positive range.
+        adc.i2c.conversion = 0x4000;
+        let volts = adc.read_voltage(0).unwrap();
+        assert!((volts - 16384.0 * 6.144 / 32767.0).abs() < 1e-9);
+        assert!(adc.read_raw(4).is_err());
+    }
+
+    #[test]
+    fn comparator_thresholds_and_bits() {
+        let mut adc = adc();
+        adc.set_comparator(Some(Comparator {
+            mode: ComparatorMode::Window,
+            active_high: true,
+            latching: true,
+            queue: ComparatorQueue::Four,
+            low: -100,
+            high: 2000,
+        }))
+        .unwrap();
+        assert_eq!(adc.i2c.last_write(REG_LO_THRESH), Some(0xff9c));
+        assert_eq!(adc.i2c.last_write(REG_HI_THRESH), Some(2000));
+        let config = single_shot_config(&mut adc, Input::Ain0);
+        assert_eq!(config & 0x1f, 0b1_1110);
+
+        adc.set_comparator(Some(Comparator::conversion_ready()))
+            .unwrap();
+        assert_eq!(adc.i2c.last_write(REG_LO_THRESH), Some(0x0000));
+        assert_eq!(adc.i2c.last_write(REG_HI_THRESH), Some(0xffff));
+        let config = single_shot_config(&mut adc, Input::Ain0);
+        assert_eq!(config & 0x1f, 0b0_0000);
+
+        adc.set_comparator(None).unwrap();
+        let config = single_shot_config(&mut adc, Input::Ain0);
+        assert_eq!(config & 0x1f, COMP_QUE_DISABLE);
+    }
+
+    #[test]
+    fn continuous_mode() {
+        let mut adc = adc();
+        adc.start_continuous(Input::Ain1).unwrap();
+        assert_eq!(adc.i2c.last_write(REG_CONFIG), Some(0x5483));
+        // Settings are rewritten straight away while converting.
+        adc.set_gain(Gain::Fsr4_096).unwrap();
+        assert_eq!(adc.i2c.last_write(REG_CONFIG), Some(0x5283));
+        adc.i2c.conversion = 0x8000;
+        assert_eq!(adc.read_continuous().unwrap(), -32768);
+        adc.stop_continuous().unwrap();
+        assert_eq!(adc.i2c.last_write(REG_CONFIG), Some(0x5383));
+    }
+}
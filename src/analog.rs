@@ -0,0 +1,155 @@
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalogReading {
+    pub raw: u16,
+    pub voltage: f64,
+}
+
+// An ADC with single-ended inputs numbered from 0.
+pub trait AnalogInput {
+    fn channels(&self) -> u8;
+    fn resolution_bits(&self) -> u8;
+    // The voltage that reads as full scale.
+    fn reference_voltage(&self) -> f64;
+    fn read_raw(&mut self, channel: u8) -> Result<u16, Box<dyn Error>>;
+
+    fn max_count(&self) -> u16 {
+        ((1u32 << self.resolution_bits()) - 1) as u16
+    }
+
+    // The reading as a fraction of full scale, 0.0..=1.0.
+    fn read_normalized(&mut self, channel: u8) -> Result<f64, Box<dyn Error>> {
+        Ok(self.read_raw(channel)? as f64 / self.max_count() as f64)
+    }
+
+    fn read_voltage(&mut self, channel: u8) -> Result<f64, Box<dyn Error>> {
+        Ok(self.read_normalized(channel)? * self.reference_voltage())
+    }
+
+    // One conversion, reported both ways. The count is worked back from the voltage,
+    // so an input that overrides `read_voltage` still gives a matching pair.
+    fn read(&mut self, channel: u8) -> Result<AnalogReading, Box<dyn Error>> {
+        let voltage = self.read_voltage(channel)?;
+        let raw = (voltage / self.reference_voltage() * self.max_count() as f64).round();
+        Ok(AnalogReading {
+            raw: raw.clamp(0.0, self.max_count() as f64) as u16,
+            voltage,
+        })
+    }
+}
+
+impl<A: AnalogInput + ?Sized> AnalogInput for Box<A> {
+    fn channels(&self) -> u8 {
+        (**self).channels()
+    }
+
+    fn resolution_bits(&self) -> u8 {
+        (**self).resolution_bits()
+    }
+
+    fn reference_voltage(&self) -> f64 {
+        (**self).reference_voltage()
+    }
+
+    fn read_raw(&mut self, channel: u8) -> Result<u16, Box<dyn Error>> {
+        (**self).read_raw(channel)
+    }
+}
+
+// Readings this close to either rail, as a fraction of the supply, are as far as a
+// divider can be trusted; past it the resistance heads for zero or infinity.
+pub(crate) const RAIL_MARGIN: f64 = 0.002;
+
+// Where a resistive sensor sits in a voltage divider, seen from the ADC input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    // Sensor between the supply and the ADC input, fixed resistor to ground.
+    HighSide,
+    // Fixed resistor from the supply, sensor to ground.
+    LowSide,
+}
+
+impl Topology {
+    // Sensor resistance from the input voltage as a fraction of the divider's supply.
+    // Goes to zero or infinity at the rails; callers decide what those mean.
+    pub fn resistance(&self, ratio: f64, series_resistor: f64) -> f64 {
+        match self {
+            Topology::LowSide => series_resistor * ratio / (1.0 - ratio),
+            Topology::HighSide => series_resistor * (1.0 - ratio) / ratio,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NoSuchChannel(pub u8);
+
+impl std::fmt::Display for NoSuchChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no analog channel {}", self.0)
+    }
+}
+
+impl Error for NoSuchChannel {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 10-bit ADC on 3.3 V whose every channel reads `raw`.
+    struct FixedAdc {
+        raw: u16,
+        conversions: u32,
+    }
+
+    impl AnalogInput for FixedAdc {
+        fn channels(&self) -> u8 {
+            1
+        }
+
+        fn resolution_bits(&self) -> u8 {
+            10
+        }
+
+        fn reference_voltage(&self) -> f64 {
+            3.3
+        }
+
+        fn read_raw(&mut self, _channel: u8) -> Result<u16, Box<dyn Error>> {
+            self.conversions += 1;
+            Ok(self.raw)
+        }
+    }
+
+    #[test]
+    fn read_reports_one_conversion_both_ways() {
+        let mut adc = FixedAdc {
+            raw: 0,
+            conversions: 0,
+        };
+        assert_eq!(adc.max_count(), 1023);
+        for raw in 0..=1023 {
+            adc.raw = raw;
+            let reading = adc.read(0).unwrap();
+            assert_eq!(reading.raw, raw);
+            assert_eq!(reading.voltage, adc.read_voltage(0).unwrap());
+        }
+        assert_eq!(adc.conversions, 2 * 1024);
+        adc.raw = 1023;
+        assert_eq!(adc.read_normalized(0).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn divider_resistance() {
+        // Half the supply means the sensor matches the series resistor either way round.
+        assert_eq!(Topology::LowSide.resistance(0.5, 10_000.0), 10_000.0);
+        assert_eq!(Topology::HighSide.resistance(0.5, 10_000.0), 10_000.0);
+        // A low-side sensor pulls the input down as it gets smaller.
+        assert!((Topology::LowSide.resistance(0.25, 10_000.0) - 10_000.0 / 3.0).abs() < 1e-9);
+        assert!((Topology::HighSide.resistance(0.25, 10_000.0) - 30_000.0).abs() < 1e-9);
+        assert_eq!(Topology::LowSide.resistance(0.0, 10_000.0), 0.0);
+        assert_eq!(Topology::LowSide.resistance(1.0, 10_000.0), f64::INFINITY);
+        assert_eq!(Topology::HighSide.resistance(1.0, 10_000.0), 0.0);
+        assert_eq!(Topology::HighSide.resistance(0.0, 10_000.0), f64::INFINITY);
+    }
+}
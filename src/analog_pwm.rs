@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analog::AnalogInput;
+use crate::pwm::PwmOutput;
+
+// Maps an input in 0.0..=1.0 to a value in the output's units.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mapping {
+    Linear {
+        out_min: f64,
+        out_max: f64,
+    },
+    // Slow at the bottom and fast at the top, like a log-taper potentiometer. `base`
+    // sets how strong the curve is; around 100 suits LED brightness.
+    Log {
+        out_min: f64,
+        out_max: f64,
+        base: f64,
+    },
+    // (input, output) points sorted by input, interpolated linearly in between.
+    Table(Vec<(f64, f64)>),
+}
+
+impl Mapping {
+    pub fn apply(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Mapping::Linear { out_min, out_max } => out_min + (out_max - out_min) * x,
+            Mapping::Log {
+                out_min,
+                out_max,
+                base,
+            } => {
+                let curve = if *base > 1.0 {
+                    (base.powf(x) - 1.0) / (base - 1.0)
+                } else {
+                    x
+                };
+                out_min + (out_max - out_min) * curve
+            }
+            Mapping::Table(points) => match points.iter().position(|(input, _)| x <= *input) {
+                None => points.last().map_or(0.0, |(_, y)| *y),
+                Some(0) => points[0].1,
+                Some(i) => {
+                    let (x0, y0) = points[i - 1];
+                    let (x1, y1) = points[i];
+                    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+                }
+            },
+        }
+    }
+}
+
+// Inputs within `width` of `center` read as exactly `center`. The rest of the range is
+// stretched so the output has no jump at the edge of the band.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadband {
+    pub center: f64,
+    pub width: f64,
+}
+
+impl Deadband {
+    pub fn apply(&self, x: f64) -> f64 {
+        let low = (self.center - self.width).max(0.0);
+        let high = (self.center + self.width).min(1.0);
+        if x < low {
+            self.center * x / low
+        } else if x > high {
+            self.center + (1.0 - self.center) * (x - high) / (1.0 - high)
+        } else {
+            self.center
+        }
+    }
+}
+
+// Reads an ADC channel and drives a PWM output from it at a fixed rate.
+pub struct AnalogToPwm<A: AnalogInput, O: PwmOutput> {
+    adc: A,
+    channel: u8,
+    output: O,
+    mapping: Mapping,
+    deadband: Option<Deadband>,
+    period: Duration,
+}
+
+impl<A: AnalogInput, O: PwmOutput> AnalogToPwm<A, O> {
+    pub fn new(adc: A, channel: u8, output: O, mapping: Mapping) -> Self {
+        AnalogToPwm {
+            adc,
+            channel,
+            output,
+            mapping,
+            deadband: None,
+            period: Duration::from_millis(20),
+        }
+    }
+
+    pub fn with_deadband(mut self, deadband: Deadband) -> Self {
+        self.deadband = Some(deadband);
+        self
+    }
+
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    // Reads once, writes the mapped value and returns it.
+    pub fn update(&mut self) -> Result<f64, Box<dyn Error>> {
+        let mut x = self.adc.read_normalized(self.channel)?;
+        if let Some(deadband) = &self.deadband {
+            x = deadband.apply(x);
+        }
+        let value = self.mapping.apply(x);
+        self.output.write(value)?;
+        Ok(value)
+    }
+
+    pub fn run(&mut self, running: &AtomicBool) -> Result<(), Box<dyn Error>> {
+        let mut next = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            self.update()?;
+            next += self.period;
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_mapping_clamps_the_input() {
+        let mapping = Mapping::Linear {
+            out_min: 10.0,
+            out_max: 20.0,
+        };
+        assert_close(mapping.apply(0.0), 10.0);
+        assert_close(mapping.apply(0.25), 12.5);
+        assert_close(mapping.apply(1.0), 20.0);
+        assert_close(mapping.apply(-0.5), 10.0);
+        assert_close(mapping.apply(1.5), 20.0);
+        // A reversed range runs backwards.
+        let reversed = Mapping::Linear {
+            out_min: 1.0,
+            out_max: 0.0,
+        };
+        assert_close(reversed.apply(0.25), 0.75);
+    }
+
+    #[test]
+    fn log_mapping_keeps_the_ends_and_bends_the_middle() {
+        let mapping = Mapping::Log {
+            out_min: 0.0,
+            out_max: 1.0,
+            base: 100.0,
+        };
+        assert_close(mapping.apply(0.0), 0.0);
+        assert_close(mapping.apply(1.0), 1.0);
+        assert_close(mapping.apply(0.5), 9.0 / 99.0);
+        // A base of 1 or less would divide by zero or invert the curve; it is linear.
+        let flat = Mapping::Log {
+            out_min: 0.0,
+            out_max: 2.0,
+            base: 1.0,
+        };
+        assert_close(flat.apply(0.5), 1.0);
+    }
+
+    #[test]
+    fn table_mapping_interpolates_between_points() {
+        let mapping = Mapping::Table(vec![(0.2, 0.0), (0.5, 10.0), (0.8, 100.0)]);
+        assert_close(mapping.apply(0.35), 5.0);
+        assert_close(mapping.apply(0.5), 10.0);
+        assert_close(mapping.apply(0.65), 55.0);
+        // Outside the table the nearest end point holds.
+        assert_close(mapping.apply(0.1), 0.0);
+        assert_close(mapping.apply(0.9), 100.0);
+        assert_close(Mapping::Table(vec![]).apply(0.5), 0.0);
+    }
+
+    #[test]
+    fn deadband_holds_the_center_and_stretches_the_rest() {
+        let deadband = Deadband {
+            center: 0.5,
+            width: 0.1,
+        };
+        assert_close(deadband.apply(0.45), 0.5);
+        assert_close(deadband.apply(0.6), 0.5);
+        assert_close(deadband.apply(0.0), 0.0);
+        assert_close(deadband.apply(0.2), 0.25);
+        assert_close(deadband.apply(0.8), 0.75);
+        assert_close(deadband.apply(1.0), 1.0);
+    }
+
+    #[test]
+    fn deadband_at_the_bottom_of_the_range() {
+        let deadband = Deadband {
+            center: 0.0,
+            width: 0.1,
+        };
+        assert_close(deadband.apply(0.0), 0.0);
+        assert_close(deadband.apply(0.05), 0.0);
+        assert_close(deadband.apply(0.55), 0.5);
+        assert_close(deadband.apply(1.0), 1.0);
+    }
+}
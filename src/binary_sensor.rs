@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{InputPin, Level};
+
+use crate::button::{self, Pull};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChange {
+    pub active: bool,
+    pub at: Instant,
+    // How long the sensor stayed in the state it just left.
+    pub previous_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BinarySensorConfig {
+    pub pull: Pull,
+    // The level the pin reads while the sensor is active.
+    pub active_level: Level,
+    // The raw level has to be stable this long before it counts.
+    pub debounce: Duration,
+    // A new state is kept at least this long before the next change is reported.
+    pub min_hold: Duration,
+}
+
+impl BinarySensorConfig {
+    // Active while tilted, i.e. while the ball switch is open and the pin reads low.
+    pub fn tilt() -> Self {
+        BinarySensorConfig {
+            pull: Pull::None,
+            active_level: Level::Low,
+            debounce: Duration::from_millis(10),
+            min_hold: Duration::from_millis(500),
+        }
+    }
+
+    pub fn slide_switch() -> Self {
+        BinarySensorConfig {
+            pull: Pull::None,
+            active_level: Level::High,
+            debounce: Duration::from_millis(20),
+            min_hold: Duration::ZERO,
+        }
+    }
+
+    // The HC-SR501 drives a clean output, so it only needs a short glitch filter.
+    pub fn pir() -> Self {
+        BinarySensorConfig {
+            pull: Pull::None,
+            active_level: Level::High,
+            debounce: Duration::from_millis(5),
+            min_hold: Duration::ZERO,
+        }
+    }
+}
+
+pub struct BinarySensorMachine {
+    config: BinarySensorConfig,
+    raw: bool,
+    raw_since: Instant,
+    active: bool,
+    since: Instant,
+}
+
+impl BinarySensorMachine {
+    pub fn new(config: BinarySensorConfig, active: bool, now: Instant) -> Self {
+        BinarySensorMachine {
+            config,
+            raw: active,
+            raw_since: now,
+            active,
+            since: now,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // When the current state was entered.
+    pub fn since(&self) -> Instant {
+        self.since
+    }
+
+    pub fn edge(&mut self, active: bool, now: Instant) {
+        if active != self.raw {
+            self.raw = active;
+            self.raw_since = now;
+        }
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Option<StateChange> {
+        let ready_at = self.next_deadline()?;
+        if now < ready_at {
+            return None;
+        }
+        let at = self.raw_since.max(self.since + self.config.min_hold);
+        let change = StateChange {
+            active: self.raw,
+            at,
+            previous_duration: at - self.since,
+        };
+        self.active = self.raw;
+        self.since = at;
+        Some(change)
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.raw == self.active {
+            return None;
+        }
+        Some((self.raw_since + self.config.debounce).max(self.since + self.config.min_hold))
+    }
+}
+
+pub struct BinarySensor {
+    pin: InputPin,
+    active_level: Level,
+    machine: BinarySensorMachine,
+}
+
+impl BinarySensor {
+    pub fn new(pin: u8, config: BinarySensorConfig) -> Result<Self, rppal::gpio::Error> {
+        let pin = config.pull.interrupt_input(pin)?;
+        let active = pin.read() == config.active_level;
+        Ok(BinarySensor {
+            pin,
+            active_level: config.active_level,
+            machine: BinarySensorMachine::new(config, active, Instant::now()),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.machine.is_active()
+    }
+
+    // Waits for the next state change. Returns `None` if `timeout` passes first.
+    pub fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<StateChange>, rppal::gpio::Error> {
+        let started = Instant::now();
+        loop {
+            let deadline = self.machine.next_deadline();
+            let level = button::wait_for_change(&mut self.pin, deadline, started, timeout)?;
+            self.machine
+                .edge(level == self.active_level, Instant::now());
+            let change = self.machine.tick(Instant::now());
+            if change.is_some() || timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Ok(change);
+            }
+        }
+    }
+
+    // Calls `callback` with every state change until `running` is cleared.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        mut callback: F,
+    ) -> Result<(), rppal::gpio::Error>
+    where
+        F: FnMut(StateChange),
+    {
+        while running.load(Ordering::SeqCst) {
+            if let Some(change) = self.poll(Some(Duration::from_millis(100)))? {
+                callback(change);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(mut self) -> Receiver<StateChange> {
+        button::spawn_events("binary sensor", move |running, send| {
+            self.run(running, send)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn config(debounce: u64, min_hold: u64) -> BinarySensorConfig {
+        BinarySensorConfig {
+            debounce: ms(debounce),
+            min_hold: ms(min_hold),
+            ..BinarySensorConfig::slide_switch()
+        }
+    }
+
+    #[test]
+    fn debounces_edges() {
+        let t0 = Instant::now();
+        let mut machine = BinarySensorMachine::new(config(20, 0), false, t0);
+        assert_eq!(machine.next_deadline(), None);
+        machine.edge(true, t0 + ms(100));
+        assert_eq!(machine.next_deadline(), Some(t0 + ms(120)));
+        assert_eq!(machine.tick(t0 + ms(119)), None);
+        // A bounce back restarts nothing: the state never left inactive.
+        machine.edge(false, t0 + ms(110));
+        assert_eq!(machine.next_deadline(), None);
+        assert_eq!(machine.tick(t0 + ms(130)), None);
+
+        machine.edge(true, t0 + ms(200));
+        assert_eq!(
+            machine.tick(t0 + ms(220)),
+            Some(StateChange {
+                active: true,
+                at: t0 + ms(200),
+                previous_duration: ms(200),
+            })
+        );
+        assert!(machine.is_active());
+        assert_eq!(machine.since(), t0 + ms(200));
+        assert_eq!(machine.tick(t0 + ms(300)), None);
+    }
+
+    #[test]
+    fn repeated_edges_keep_the_first_time() {
+        let t0 = Instant::now();
+        let mut machine = BinarySensorMachine::new(config(20, 0), false, t0);
+        machine.edge(true, t0 + ms(10));
+        machine.edge(true, t0 + ms(25));
+        assert_eq!(machine.next_deadline(), Some(t0 + ms(30)));
+    }
+
+    #[test]
+    fn min_hold_delays_the_next_change() {
+        let t0 = Instant::now();
+        let mut machine = BinarySensorMachine::new(config(10, 500), false, t0);
+        machine.edge(true, t0 + ms(1000));
+        let change = machine.tick(t0 + ms(1010)).unwrap();
+        assert_eq!(change.at, t0 + ms(1000));
+
+        // Back to inactive 100 ms later: held until 500 ms after the last change.
+        machine.edge(false, t0 + ms(1100));
+        assert_eq!(machine.next_deadline(), Some(t0 + ms(1500)));
+        assert_eq!(machine.tick(t0 + ms(1200)), None);
+        assert_eq!(
+            machine.tick(t0 + ms(1500)),
+            Some(StateChange {
+                active: false,
+                at: t0 + ms(1500),
+                previous_duration: ms(500),
+            })
+        );
+
+        // A flicker that returns within the hold is never reported.
+        machine.edge(true, t0 + ms(1600));
+        machine.edge(false, t0 + ms(1700));
+        assert_eq!(machine.tick(t0 + ms(2100)), None);
+        assert!(!machine.is_active());
+    }
+
+    #[test]
+    fn previous_duration_spans_the_state() {
+        let t0 = Instant::now();
+        let mut machine = BinarySensorMachine::new(config(10, 0), true, t0);
+        machine.edge(false, t0 + ms(3000));
+        let change = machine.tick(t0 + ms(3010)).unwrap();
+        assert_eq!(change.previous_duration, ms(3000));
+        machine.edge(true, t0 + ms(3250));
+        let change = machine.tick(t0 + ms(4000)).unwrap();
+        assert_eq!(
+            (change.at, change.previous_duration),
+            (t0 + ms(3250), ms(250))
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level, Trigger};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+impl Pull {
+    // `pin` as an input with this pull, interrupting on both edges.
+    pub(crate) fn interrupt_input(self, pin: u8) -> Result<InputPin, rppal::gpio::Error> {
+        let pin = Gpio::new()?.get(pin)?;
+        let mut pin = match self {
+            Pull::None => pin.into_input(),
+            Pull::Up => pin.into_input_pullup(),
+            Pull::Down => pin.into_input_pulldown(),
+        };
+        pin.set_interrupt(Trigger::Both)?;
+        Ok(pin)
+    }
+}
+
+// Waits for an edge on `pin`, for `deadline`, or for `timeout` after `started`,
+// whichever comes first, and returns the level the pin reads then. Edges queued
+// while the caller was busy are kept, and the pin itself is read rather than the
+// level of a possibly stale edge, so a change is never lost.
+pub(crate) fn wait_for_change(
+    pin: &mut InputPin,
+    deadline: Option<Instant>,
+    started: Instant,
+    timeout: Option<Duration>,
+) -> Result<Level, rppal::gpio::Error> {
+    let now = Instant::now();
+    let mut wait = deadline.map(|d| d.saturating_duration_since(now));
+    if let Some(timeout) = timeout {
+        let left = timeout.saturating_sub(now - started);
+        wait = Some(wait.map_or(left, |w| w.min(left)));
+    }
+    pin.poll_interrupt(false, wait)?;
+    Ok(pin.read())
+}
+
+// Runs `run` on a background thread, delivering what it reports on a channel. The
+// thread ends when the receiver is dropped, or on an error, which is printed since
+// nobody is left to return it to.
+pub(crate) fn spawn_events<T, E, F>(name: &'static str, run: F) -> Receiver<T>
+where
+    T: Send + 'static,
+    E: fmt::Display,
+    F: FnOnce(&AtomicBool, &mut dyn FnMut(T)) -> Result<(), E> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let running = AtomicBool::new(true);
+        let result = run(&running, &mut |event| {
+            if tx.send(event).is_err() {
+                running.store(false, Ordering::SeqCst);
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("{} stopped: {}", name, e);
+        }
+    });
+    rx
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    Click,
+    DoubleClick,
+    LongPress(Duration),
+    // Sent every `hold_repeat` after a long press, counting from 1.
+    Hold(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonConfig {
+    pub pull: Pull,
+    // The level the pin reads while the button is pressed.
+    pub active_level: Level,
+    pub debounce: Duration,
+    // A second click within this window after the first one is a double click. Single
+    // clicks are only reported once the window has passed.
+    pub double_click: Duration,
+    pub long_press: Duration,
+    pub hold_repeat: Duration,
+}
+
+impl Default for ButtonConfig {
+    // A switch to ground with the internal pull-up, as on the kit's button board.
+    fn default() -> Self {
+        ButtonConfig {
+            pull: Pull::Up,
+            active_level: Level::Low,
+            debounce: Duration::from_millis(20),
+            double_click: Duration::from_millis(300),
+            long_press: Duration::from_millis(800),
+            hold_repeat: Duration::from_millis(200),
+        }
+    }
+}
+
+// The button logic without the pin: feed it raw levels with `edge` and call `tick`
+// to collect events. Useful for testing and for pins read some other way.
+pub struct ButtonMachine {
+    config: ButtonConfig,
+    raw: bool,
+    raw_since: Instant,
+    pressed: bool,
+    pressed_at: Instant,
+    long_pressed: bool,
+    holds: u32,
+    pending_click: Option<Instant>,
+}
+
+impl ButtonMachine {
+    pub fn new(config: ButtonConfig, pressed: bool, now: Instant) -> Self {
+        ButtonMachine {
+            config,
+            raw: pressed,
+            raw_since: now,
+            pressed,
+            pressed_at: now,
+            long_pressed: false,
+            holds: 0,
+            pending_click: None,
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    pub fn edge(&mut self, pressed: bool, now: Instant) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now;
+        }
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Vec<ButtonEvent> {
+        let mut events = vec![];
+        if self.raw != self.pressed && now >= self.raw_since + self.config.debounce {
+            self.pressed = self.raw;
+            if self.pressed {
+                self.pressed_at = self.raw_since;
+                self.long_pressed = false;
+                self.holds = 0;
+                events.push(ButtonEvent::Pressed);
+            } else {
+                events.push(ButtonEvent::Released);
+                if !self.long_pressed {
+                    match self.pending_click.take() {
+                        Some(_) => events.push(ButtonEvent::DoubleClick),
+                        None if self.config.double_click.is_zero() => {
+                            events.push(ButtonEvent::Click)
+                        }
+                        None => self.pending_click = Some(self.raw_since),
+                    }
+                }
+            }
+        }
+        if let Some(released) = self.pending_click {
+            if now >= released + self.config.double_click
+                && !self.pressed
+                && !self.second_press_pending(released)
+            {
+                self.pending_click = None;
+                events.push(ButtonEvent::Click);
+            }
+        }
+        if self.pressed {
+            let held = now - self.pressed_at;
+            if !self.long_pressed && held >= self.config.long_press {
+                self.long_pressed = true;
+                // A long press cancels a click that was waiting for its second half.
+                self.pending_click = None;
+                events.push(ButtonEvent::LongPress(held));
+            }
+            while self.long_pressed && !self.config.hold_repeat.is_zero() && now >= self.next_hold()
+            {
+                self.holds += 1;
+                events.push(ButtonEvent::Hold(self.holds));
+            }
+        }
+        events
+    }
+
+    // The next moment `tick` may produce an event without a new edge.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let mut deadlines = vec![];
+        if self.raw != self.pressed {
+            deadlines.push(self.raw_since + self.config.debounce);
+        }
+        if let Some(released) = self.pending_click {
+            if !self.second_press_pending(released) {
+                deadlines.push(released + self.config.double_click);
+            }
+        }
+        if self.pressed {
+            if !self.long_pressed {
+                deadlines.push(self.pressed_at + self.config.long_press);
+            } else if !self.config.hold_repeat.is_zero() {
+                deadlines.push(self.next_hold());
+            }
+        }
+        deadlines.into_iter().min()
+    }
+
+    // A press that began inside the double-click window but is still being debounced
+    // decides whether the waiting click is single or double.
+    fn second_press_pending(&self, released: Instant) -> bool {
+        self.raw && !self.pressed && self.raw_since < released + self.config.double_click
+    }
+
+    fn next_hold(&self) -> Instant {
+        self.pressed_at + self.config.long_press + self.config.hold_repeat * (self.holds + 1)
+    }
+}
+
+pub struct Button {
+    pin: InputPin,
+    active_level: Level,
+    machine: ButtonMachine,
+}
+
+impl Button {
+    pub fn new(pin: u8, config: ButtonConfig) -> Result<Self, rppal::gpio::Error> {
+        let pin = config.pull.interrupt_input(pin)?;
+        let pressed = pin.read() == config.active_level;
+        Ok(Button {
+            pin,
+            active_level: config.active_level,
+            machine: ButtonMachine::new(config, pressed, Instant::now()),
+        })
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.machine.is_pressed()
+    }
+
+    // Waits for the next events. Returns an empty list if `timeout` passes first.
+    pub fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ButtonEvent>, rppal::gpio::Error> {
+        let started = Instant::now();
+        loop {
+            let deadline = self.machine.next_deadline();
+            let level = wait_for_change(&mut self.pin, deadline, started, timeout)?;
+            self.machine
+                .edge(level == self.active_level, Instant::now());
+            let events = self.machine.tick(Instant::now());
+            if !events.is_empty() || timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Ok(events);
+            }
+        }
+    }
+
+    // Calls `callback` with every event until `running` is cleared.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        mut callback: F,
+    ) -> Result<(), rppal::gpio::Error>
+    where
+        F: FnMut(ButtonEvent),
+    {
+        while running.load(Ordering::SeqCst) {
+            for event in self.poll(Some(Duration::from_millis(100)))? {
+                callback(event);
+            }
+        }
+        Ok(())
+    }
+
+    // Moves the button to a background thread and delivers its events on a channel.
+    // The thread ends when the receiver is dropped.
+    pub fn spawn(mut self) -> Receiver<ButtonEvent> {
+        spawn_events("button", move |running, send| self.run(running, send))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Plays raw levels into a machine, ticking every millisecond like a busy caller.
+    struct Script {
+        machine: ButtonMachine,
+        start: Instant,
+        now: u64,
+    }
+
+    impl Script {
+        fn new(config: ButtonConfig) -> Self {
+            let start = Instant::now();
+            Script {
+                machine: ButtonMachine::new(config, false, start),
+                start,
+                now: 0,
+            }
+        }
+
+        // Holds the raw level at `pressed` until `until` ms, collecting events.
+        fn level(&mut self, pressed: bool, until: u64) -> Vec<(u64, ButtonEvent)> {
+            self.machine.edge(pressed, self.start + ms(self.now));
+            let mut events = vec![];
+            while self.now < until {
+                self.now += 1;
+                for event in self.machine.tick(self.start + ms(self.now)) {
+                    events.push((self.now, event));
+                }
+            }
+            events
+        }
+    }
+
+    fn kinds(events: &[(u64, ButtonEvent)]) -> Vec<ButtonEvent> {
+        events.iter().map(|(_, event)| *event).collect()
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut script = Script::new(ButtonConfig::default());
+        // Contact bounce shorter than the 20 ms debounce, then a real press.
+        assert!(script.level(true, 5).is_empty());
+        assert!(script.level(false, 8).is_empty());
+        assert!(script.level(true, 12).is_empty());
+        assert!(script.level(false, 20).is_empty());
+        let events = script.level(true, 100);
+        assert_eq!(events, vec![(40, ButtonEvent::Pressed)]);
+        assert!(script.machine.is_pressed());
+    }
+
+    #[test]
+    fn click_waits_out_the_double_click_window() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        let events = script.level(false, 1000);
+        // Released at 100, debounced at 120, the window runs from the release edge.
+        assert_eq!(
+            events,
+            [(120, ButtonEvent::Released), (400, ButtonEvent::Click)]
+        );
+    }
+
+    #[test]
+    fn click_is_immediate_without_double_click() {
+        let mut script = Script::new(ButtonConfig {
+            double_click: Duration::ZERO,
+            ..ButtonConfig::default()
+        });
+        script.level(true, 100);
+        assert_eq!(
+            kinds(&script.level(false, 200)),
+            [ButtonEvent::Released, ButtonEvent::Click]
+        );
+    }
+
+    #[test]
+    fn double_click() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        script.level(false, 250);
+        script.level(true, 350);
+        assert_eq!(
+            kinds(&script.level(false, 1000)),
+            [ButtonEvent::Released, ButtonEvent::DoubleClick]
+        );
+    }
+
+    #[test]
+    fn second_press_just_inside_the_window_is_a_double_click() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        script.level(false, 395);
+        // Pressed 5 ms before the window closes at 400, debounced only after it.
+        let events = script.level(true, 500);
+        assert_eq!(kinds(&events), [ButtonEvent::Pressed]);
+        assert_eq!(
+            kinds(&script.level(false, 1200)),
+            [ButtonEvent::Released, ButtonEvent::DoubleClick]
+        );
+    }
+
+    #[test]
+    fn bounce_at_the_end_of_the_window_leaves_a_click() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        script.level(false, 395);
+        assert!(script.level(true, 405).is_empty());
+        // Ticks resume a millisecond after each scripted edge.
+        assert_eq!(script.level(false, 1000), [(406, ButtonEvent::Click)]);
+    }
+
+    #[test]
+    fn press_after_the_window_is_a_new_click() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        assert_eq!(
+            kinds(&script.level(false, 450)),
+            [ButtonEvent::Released, ButtonEvent::Click]
+        );
+        assert_eq!(kinds(&script.level(true, 550)), [ButtonEvent::Pressed]);
+        assert_eq!(
+            kinds(&script.level(false, 1200)),
+            [ButtonEvent::Released, ButtonEvent::Click]
+        );
+    }
+
+    #[test]
+    fn long_press_and_hold_repeat() {
+        let mut script = Script::new(ButtonConfig::default());
+        // Pressed at 0 (debounced at 20): long press at 800, holds every 200 ms.
+        let events = script.level(true, 1450);
+        assert_eq!(
+            events,
+            [
+                (20, ButtonEvent::Pressed),
+                (800, ButtonEvent::LongPress(ms(800))),
+                (1000, ButtonEvent::Hold(1)),
+                (1200, ButtonEvent::Hold(2)),
+                (1400, ButtonEvent::Hold(3)),
+            ]
+        );
+        // No click after a long press.
+        assert_eq!(kinds(&script.level(false, 2000)), [ButtonEvent::Released]);
+    }
+
+    #[test]
+    fn long_press_cancels_a_waiting_click() {
+        let mut script = Script::new(ButtonConfig::default());
+        script.level(true, 100);
+        script.level(false, 200);
+        let events = script.level(true, 1100);
+        assert_eq!(
+            kinds(&events),
+            [ButtonEvent::Pressed, ButtonEvent::LongPress(ms(800))]
+        );
+        assert_eq!(kinds(&script.level(false, 2000)), [ButtonEvent::Released]);
+    }
+
+    #[test]
+    fn deadlines_follow_the_state() {
+        let config = ButtonConfig::default();
+        let start = Instant::now();
+        let mut machine = ButtonMachine::new(config, false, start);
+        assert_eq!(machine.next_deadline(), None);
+        machine.edge(true, start);
+        assert_eq!(machine.next_deadline(), Some(start + ms(20)));
+        machine.tick(start + ms(20));
+        assert_eq!(machine.next_deadline(), Some(start + ms(800)));
+        machine.tick(start + ms(800));
+        assert_eq!(machine.next_deadline(), Some(start + ms(1000)));
+        machine.edge(false, start + ms(900));
+        machine.tick(start + ms(920));
+        assert_eq!(machine.next_deadline(), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, Level, Mode};
+
+use crate::temperature::Temperature;
+use crate::timing::{self, Realtime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    // Relative humidity in percent.
+    pub humidity: f64,
+    pub temperature: Temperature,
+    pub timestamp: Instant,
+}
+
+#[derive(Debug)]
+pub enum DhtError {
+    GpioError(rppal::gpio::Error),
+    TimeOut,
+    CheckSum,
+}
+
+impl fmt::Display for DhtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DhtError::GpioError(e) => write!(f, "gpio error: {}", e),
+            DhtError::TimeOut => write!(f, "timed out waiting for the sensor"),
+            DhtError::CheckSum => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+impl Error for DhtError {}
+
+impl From<rppal::gpio::Error> for DhtError {
+    fn from(e: rppal::gpio::Error) -> DhtError {
+        DhtError::GpioError(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dht11,
+    // Also sold as the AM2302.
+    Dht22,
+}
+
+impl Model {
+    // How long the host holds the line low to wake the sensor.
+    pub fn start_pulse(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_millis(18),
+            Model::Dht22 => Duration::from_micros(1100),
+        }
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        match self {
+            Model::Dht11 => Duration::from_secs(1),
+            Model::Dht22 => Duration::from_secs(2),
+        }
+    }
+}
+
+// Checks the checksum of the 40 bits the sensor sent and returns the relative
+// humidity in percent and the temperature.
+pub fn decode(model: Model, bytes: &[u8; 5]) -> Result<(f64, Temperature), DhtError> {
+    let sum: u16 = bytes.iter().take(4).map(|b| *b as u16).sum();
+    if bytes[4] as u16 != sum & 0x00FF {
+        return Err(DhtError::CheckSum);
+    }
+    let (humidity, celsius, negative) = match model {
+        // Integral and decimal bytes. Parts that go below zero flag it in the top bit
+        // of the temperature decimal.
+        Model::Dht11 => (
+            bytes[0] as f64 + bytes[1] as f64 / 10.0,
+            bytes[2] as f64 + (bytes[3] & 0x7f) as f64 / 10.0,
+            bytes[3] & 0x80 != 0,
+        ),
+        // Tenths in 16 bits, with a sign bit on top of the temperature.
+        Model::Dht22 => (
+            u16::from_be_bytes([bytes[0], bytes[1]]) as f64 / 10.0,
+            u16::from_be_bytes([bytes[2] & 0x7f, bytes[3]]) as f64 / 10.0,
+            bytes[2] & 0x80 != 0,
+        ),
+    };
+    let celsius = if negative { -celsius } else { celsius };
+    Ok((humidity, Temperature::from_celsius(celsius)))
+}
+
+// The 40 bits of a transfer from the widths of their high pulses, most significant
+// bit first. Fewer or more than 40 pulses means the transfer was cut short or
+// picked up noise.
+pub fn bytes_from_pulses(widths: &[Duration]) -> Result<[u8; 5], DhtError> {
+    if widths.len() != 40 {
+        return Err(DhtError::TimeOut);
+    }
+    let mut bytes = [0u8; 5];
+    for (i, width) in widths.iter().enumerate() {
+        if *width > Duration::from_micros(50) {
+            bytes[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+    Ok(bytes)
+}
+
+pub struct Dht {
+    pin: u8,
+    model: Model,
+    retries: u32,
+    min_interval: Duration,
+    last_attempt: Option<Instant>,
+    last_reading: Option<Reading>,
+    realtime: Option<Realtime>,
+}
+
+impl Dht {
+    pub fn new(pin: u8, model: Model) -> Self {
+        Dht {
+            pin,
+            model,
+            retries: 3,
+            min_interval: model.min_interval(),
+            last_attempt: None,
+            last_reading: None,
+            realtime: None,
+        }
+    }
+
+    // Extra attempts after a timeout or a checksum error.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    // The sensor does not answer if asked again sooner than this. Defaults to the
+    // model's own minimum.
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    // Runs each transfer under SCHED_FIFO, see `timing::Realtime`.
+    pub fn with_realtime(mut self, realtime: Realtime) -> Self {
+        self.realtime = Some(realtime);
+        self
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn last_reading(&self) -> Option<Reading> {
+        self.last_reading
+    }
+
+    // Within `min_interval` of the last attempt this returns the cached reading, or
+    // waits out the interval when there is none. Check `timestamp` for its age.
+    pub fn read(&mut self) -> Result<Reading, DhtError> {
+        let mut attempt = 0;
+        loop {
+            if let Some(last) = self.last_attempt {
+                let ready = last + self.min_interval;
+                match self.last_reading {
+                    Some(reading) if attempt == 0 && Instant::now() < ready => return Ok(reading),
+                    _ => {}
+                }
+                thread::sleep(ready.saturating_duration_since(Instant::now()));
+            }
+            self.last_attempt = Some(Instant::now());
+            match self.read_once() {
+                Ok(reading) => {
+                    self.last_reading = Some(reading);
+                    return Ok(reading);
+                }
+                Err(DhtError::GpioError(e)) => return Err(DhtError::GpioError(e)),
+                Err(e) if attempt >= self.retries => return Err(e),
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    fn read_once(&self) -> Result<Reading, DhtError> {
+        let mut pin = Gpio::new()?.get(self.pin)?.into_io(Mode::Output);
+        let widths = {
+            // Best effort: without the privileges this runs at normal priority and
+            // relies on the retries.
+            let _realtime = self.realtime.and_then(|r| r.enter().ok());
+
+            // Hold the line low to wake the sensor, then release it to the pull-up.
+            pin.set_low();
+            timing::delay(self.model.start_pulse());
+            pin.set_mode(Mode::Input);
+
+            // The sensor answers 80 µs low and 80 µs high, then sends each bit as 50 µs
+            // low followed by a high pulse of 26-28 µs for a 0 or 70 µs for a 1.
+            let timeout = Duration::from_micros(200);
+            for level in [Level::Low, Level::High, Level::Low] {
+                timing::wait_for_level(|| pin.read(), level, timeout).ok_or(DhtError::TimeOut)?;
+            }
+            let mut widths = [Duration::ZERO; 40];
+            for width in widths.iter_mut() {
+                *width = timing::pulse_width(|| pin.read(), Level::High, timeout)
+                    .ok_or(DhtError::TimeOut)?;
+            }
+            widths
+        };
+
+        let (humidity, temperature) = decode(self.model, &bytes_from_pulses(&widths)?)?;
+        Ok(Reading {
+            humidity,
+            temperature,
+            timestamp: Instant::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // High pulse widths as a sensor sends them, with a little jitter.
+    fn pulses(bytes: [u8; 5]) -> Vec<Duration> {
+        (0..40)
+            .map(|i| {
+                let jitter = (i % 3) as u64 * 2;
+                if bytes[i / 8] & (0x80 >> (i % 8)) != 0 {
+                    Duration::from_micros(68 + jitter)
+                } else {
+                    Duration::from_micros(24 + jitter)
+                }
+            })
+            .collect()
+    }
+
+    fn read(model: Model, widths: &[Duration]) -> Result<(f64, Temperature), DhtError> {
+        decode(model, &bytes_from_pulses(widths)?)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn dht11_frame() {
+        // 55.0 %, 24.3 °C.
+        let frame = [0x37, 0x00, 0x18, 0x03, 0x52];
+        assert_eq!(bytes_from_pulses(&pulses(frame)).unwrap(), frame);
+        let (humidity, temperature) = read(Model::Dht11, &pulses(frame)).unwrap();
+        assert_close(humidity, 55.0);
+        assert_close(temperature.celsius(), 24.3);
+    }
+
+    #[test]
+    fn dht22_frame() {
+        // 65.2 %, 35.1 °C.
+        let frame = [0x02, 0x8c, 0x01, 0x5f, 0xee];
+        let (humidity, temperature) = read(Model::Dht22, &pulses(frame)).unwrap();
+        assert_close(humidity, 65.2);
+        assert_close(temperature.celsius(), 35.1);
+    }
+
+    #[test]
+    fn dht22_below_zero() {
+        // 43.6 %, sign bit with 0x0065 for -10.1 °C.
+        let frame = [0x01, 0xb4, 0x80, 0x65, 0x9a];
+        let (humidity, temperature) = read(Model::Dht22, &pulses(frame)).unwrap();
+        assert_close(humidity, 43.6);
+        assert_close(temperature.celsius(), -10.1);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let frame = [0x02, 0x8c, 0x01, 0x5f, 0xef];
+        assert!(matches!(
+            read(Model::Dht22, &pulses(frame)),
+            Err(DhtError::CheckSum)
+        ));
+    }
+
+    #[test]
+    fn short_train() {
+        let widths = pulses([0x37, 0x00, 0x18, 0x03, 0x52]);
+        assert!(matches!(
+            read(Model::Dht11, &widths[..39]),
+            Err(DhtError::TimeOut)
+        ));
+        assert!(matches!(read(Model::Dht11, &[]), Err(DhtError::TimeOut)));
+    }
+
+    #[test]
+    fn garbled_train() {
+        // A glitch read as an extra leading bit shifts the whole frame.
+        let mut widths = pulses([0x37, 0x00, 0x18, 0x03, 0x52]);
+        widths.insert(0, Duration::from_micros(70));
+        widths.pop();
+        assert!(matches!(
+            read(Model::Dht11, &widths),
+            Err(DhtError::CheckSum)
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::temperature::Temperature;
+
+// Needs `dtoverlay=w1-gpio` in config.txt; the data line defaults to GPIO4.
+pub const DEFAULT_ROOT: &str = "/sys/bus/w1/devices";
+
+// The DS18B20's family code.
+const FAMILY_PREFIX: &str = "28-";
+
+#[derive(Debug)]
+pub enum Ds18b20Error {
+    IoError(io::Error),
+    // The kernel read the scratchpad but its CRC did not match, usually noise on
+    // a long cable.
+    CrcMismatch,
+    // 85 °C is the power-on value of the register, read when the sensor browned
+    // out before finishing a conversion.
+    PowerOnReset,
+    Parse(String),
+}
+
+impl fmt::Display for Ds18b20Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ds18b20Error::IoError(e) => write!(f, "io error: {}", e),
+            Ds18b20Error::CrcMismatch => write!(f, "crc mismatch"),
+            Ds18b20Error::PowerOnReset => write!(f, "sensor reset during conversion"),
+            Ds18b20Error::Parse(s) => write!(f, "cannot parse '{}'", s),
+        }
+    }
+}
+
+impl Error for Ds18b20Error {}
+
+impl From<io::Error> for Ds18b20Error {
+    fn from(e: io::Error) -> Ds18b20Error {
+        Ds18b20Error::IoError(e)
+    }
+}
+
+// `w1_slave` holds two lines of scratchpad bytes, the first ending in the CRC
+// verdict and the second in the temperature in millidegrees:
+//
+//   72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+//   72 01 4b 46 7f ff 0e 10 57 t=23125
+pub fn parse_w1_slave(text: &str) -> Result<Temperature, Ds18b20Error> {
+    let mut lines = text.lines();
+    let crc = lines.next().unwrap_or("").trim_end();
+    if crc.ends_with("NO") {
+        return Err(Ds18b20Error::CrcMismatch);
+    }
+    if !crc.ends_with("YES") {
+        return Err(Ds18b20Error::Parse(crc.to_string()));
+    }
+    let data = lines.next().unwrap_or("").trim_end();
+    match data.rsplit_once("t=") {
+        Some((_, value)) => parse_millidegrees(value),
+        None => Err(Ds18b20Error::Parse(data.to_string())),
+    }
+}
+
+// `temperature` holds just the millidegrees. The kernel fails the read instead of
+// reporting a bad CRC.
+pub fn parse_temperature(text: &str) -> Result<Temperature, Ds18b20Error> {
+    parse_millidegrees(text.trim())
+}
+
+fn parse_millidegrees(value: &str) -> Result<Temperature, Ds18b20Error> {
+    let millis: i32 = value
+        .trim()
+        .parse()
+        .map_err(|_| Ds18b20Error::Parse(value.to_string()))?;
+    if millis == 85_000 {
+        return Err(Ds18b20Error::PowerOnReset);
+    }
+    Ok(Temperature::from_celsius(millis as f64 / 1000.0))
+}
+
+#[derive(Debug)]
+pub struct SensorReading {
+    pub rom_id: String,
+    pub name: Option<String>,
+    pub temperature: Result<Temperature, Ds18b20Error>,
+}
+
+pub struct Ds18b20Bus {
+    root: PathBuf,
+    names: HashMap<String, String>,
+}
+
+impl Ds18b20Bus {
+    pub fn new() -> Self {
+        Self::with_root(DEFAULT_ROOT)
+    }
+
+    // For a different mount point, or a fake directory tree.
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        Ds18b20Bus {
+            root: root.as_ref().to_path_buf(),
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn with_name(mut self, rom_id: &str, name: &str) -> Self {
+        self.names.insert(rom_id.to_string(), name.to_string());
+        self
+    }
+
+    // One `<rom id> <name>` per line, e.g. `28-0316a2799aff attic`. Blank lines and
+    // lines starting with `#` are skipped.
+    pub fn load_names<P: AsRef<Path>>(mut self, path: P) -> Result<Self, Ds18b20Error> {
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some((rom_id, name)) => {
+                    self.names
+                        .insert(rom_id.to_string(), name.trim().to_string());
+                }
+                None => return Err(Ds18b20Error::Parse(line.to_string())),
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn name(&self, rom_id: &str) -> Option<&str> {
+        self.names.get(rom_id).map(String::as_str)
+    }
+
+    // ROM IDs of the DS18B20s the kernel has found, sorted.
+    pub fn discover(&self) -> Result<Vec<String>, Ds18b20Error> {
+        let mut ids = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with(FAMILY_PREFIX) {
+                ids.push(name);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    // Each read waits out a conversion of up to 750 ms.
+    pub fn read(&self, rom_id: &str) -> Result<Temperature, Ds18b20Error> {
+        let dir = self.root.join(rom_id);
+        match fs::read_to_string(dir.join("w1_slave")) {
+            Ok(text) => parse_w1_slave(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                parse_temperature(&fs::read_to_string(dir.join("temperature"))?)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Reads every sensor found, all at once, so the conversions overlap.
+    pub fn read_all(&self) -> Result<Vec<SensorReading>, Ds18b20Error> {
+        let ids = self.discover()?;
+        Ok(thread::scope(|scope| {
+            let handles: Vec<_> = ids
+                .into_iter()
+                .map(|rom_id| scope.spawn(move || (self.read(&rom_id), rom_id)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    let (temperature, rom_id) = handle.join().expect("sensor thread panicked");
+                    SensorReading {
+                        name: self.name(&rom_id).map(str::to_string),
+                        rom_id,
+                        temperature,
+                    }
+                })
+                .collect()
+        }))
+    }
+}
+
+impl Default for Ds18b20Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fake `/sys/bus/w1/devices`, removed again on drop.
+    struct FakeRoot(PathBuf);
+
+    impl FakeRoot {
+        fn new(test: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("ds18b20-{}-{}", std::process::id(), test));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            FakeRoot(path)
+        }
+
+        fn add(&self, rom_id: &str, file: &str, text: &str) -> &Self {
+            let dir = self.0.join(rom_id);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(file), text).unwrap();
+            self
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn w1_slave(crc: &str, millis: &str) -> String {
+        format!(
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 {}\n72 01 4b 46 7f ff 0e 10 57 t={}\n",
+            crc, millis
+        )
+    }
+
+    #[test]
+    fn parses_w1_slave() {
+        let celsius = parse_w1_slave(&w1_slave("YES", "23125")).unwrap().celsius();
+        assert!((celsius - 23.125).abs() < 1e-9);
+        let celsius = parse_w1_slave(&w1_slave("YES", "-1250")).unwrap().celsius();
+        assert!((celsius + 1.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn w1_slave_errors() {
+        assert!(matches!(
+            parse_w1_slave(&w1_slave("NO", "23125")),
+            Err(Ds18b20Error::CrcMismatch)
+        ));
+        assert!(matches!(
+            parse_w1_slave(&w1_slave("YES", "85000")),
+            Err(Ds18b20Error::PowerOnReset)
+        ));
+        assert!(matches!(
+            parse_w1_slave(&w1_slave("YES", "abc")),
+            Err(Ds18b20Error::Parse(_))
+        ));
+        assert!(matches!(parse_w1_slave(""), Err(Ds18b20Error::Parse(_))));
+        assert!(matches!(
+            parse_w1_slave("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n"),
+            Err(Ds18b20Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn discovers_sorted_ds18b20s_only() {
+        let root = FakeRoot::new("discover");
+        root.add("28-0316a2799aff", "w1_slave", &w1_slave("YES", "20000"))
+            .add("28-01144fb5a1aa", "w1_slave", &w1_slave("YES", "21000"))
+            .add("w1_bus_master1", "w1_master_slave_count", "2\n")
+            .add("10-000802b4c7e1", "w1_slave", &w1_slave("YES", "22000"));
+        let bus = Ds18b20Bus::with_root(&root.0);
+        assert_eq!(
+            bus.discover().unwrap(),
+            vec!["28-01144fb5a1aa", "28-0316a2799aff"]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_temperature() {
+        let root = FakeRoot::new("fallback");
+        root.add("28-0000075a1b2c", "temperature", "-1250\n");
+        let bus = Ds18b20Bus::with_root(&root.0);
+        let celsius = bus.read("28-0000075a1b2c").unwrap().celsius();
+        assert!((celsius + 1.25).abs() < 1e-9);
+        assert!(matches!(
+            bus.read("28-ffffffffffff"),
+            Err(Ds18b20Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn reads_all_with_names() {
+        let root = FakeRoot::new("read-all");
+        root.add("28-000000000001", "w1_slave", &w1_slave("YES", "21500"))
+            .add("28-000000000002", "w1_slave", &w1_slave("NO", "21500"))
+            .add("28-000000000003", "w1_slave", &w1_slave("YES", "85000"))
+            .add("28-000000000004", "temperature", "-1250\n");
+        let bus = Ds18b20Bus::with_root(&root.0)
+            .with_name("28-000000000001", "attic")
+            .with_name("28-000000000004", "freezer");
+        let readings = bus.read_all().unwrap();
+
+        let ids: Vec<_> = readings.iter().map(|r| r.rom_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "28-000000000001",
+                "28-000000000002",
+                "28-000000000003",
+                "28-000000000004"
+            ]
+        );
+        let names: Vec<_> = readings.iter().map(|r| r.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("attic"), None, None, Some("freezer")]);
+        assert!(readings[0].temperature.is_ok());
+        assert!(matches!(
+            readings[1].temperature,
+            Err(Ds18b20Error::CrcMismatch)
+        ));
+        assert!(matches!(
+            readings[2].temperature,
+            Err(Ds18b20Error::PowerOnReset)
+        ));
+        let celsius = readings[3].temperature.as_ref().unwrap().celsius();
+        assert!((celsius + 1.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn loads_names() {
+        let root = FakeRoot::new("names");
+        let path = root.0.join("names.txt");
+        fs::write(
+            &path,
+            "# sensors\n\n28-0316a2799aff  attic\n28-01144fb5a1aa porch light\n",
+        )
+        .unwrap();
+        let bus = Ds18b20Bus::with_root(&root.0).load_names(&path).unwrap();
+        assert_eq!(bus.name("28-0316a2799aff"), Some("attic"));
+        assert_eq!(bus.name("28-01144fb5a1aa"), Some("porch light"));
+
+        fs::write(&path, "28-0316a2799aff\n").unwrap();
+        assert!(matches!(
+            Ds18b20Bus::with_root(&root.0).load_names(&path),
+            Err(Ds18b20Error::Parse(_))
+        ));
+    }
+}
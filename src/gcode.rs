@@ -0,0 +1,412 @@
+use std::error::Error;
+use std::fmt;
+use std::io::BufRead;
+use std::time::Duration;
+
+use crate::stepper::MultiStepper;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Target {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub f: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Rapid(Target),
+    Linear(Target),
+    Home,
+    EnableMotors,
+    DisableMotors,
+}
+
+#[derive(Debug)]
+pub enum GcodeError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    Unsupported { line: usize, word: String },
+}
+
+impl fmt::Display for GcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GcodeError::Io(e) => write!(f, "io error: {}", e),
+            GcodeError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            GcodeError::Unsupported { line, word } => {
+                write!(f, "line {}: unsupported command {}", line, word)
+            }
+        }
+    }
+}
+
+impl Error for GcodeError {}
+
+impl From<std::io::Error> for GcodeError {
+    fn from(e: std::io::Error) -> GcodeError {
+        GcodeError::Io(e)
+    }
+}
+
+fn strip_comments(line: &str) -> String {
+    let line = line.split(';').next().unwrap_or("");
+    let mut out = String::new();
+    let mut in_paren = false;
+    for c in line.chars() {
+        match c {
+            '(' => in_paren = true,
+            ')' => in_paren = false,
+            _ if !in_paren => out.push(c),
+            _ => (),
+        }
+    }
+    out
+}
+
+fn words(line: &str, line_no: usize) -> Result<Vec<(char, f64)>, GcodeError> {
+    let line = strip_comments(line).to_ascii_uppercase();
+    let mut words = vec![];
+    let mut chars = line.chars().filter(|c| !c.is_whitespace()).peekable();
+    while let Some(letter) = chars.next() {
+        if !letter.is_ascii_alphabetic() {
+            return Err(GcodeError::Parse {
+                line: line_no,
+                message: format!("unexpected '{}'", letter),
+            });
+        }
+        let mut number = String::new();
+        while let Some(c) = chars.peek() {
+            if c.is_ascii_digit() || *c == '.' || *c == '-' || *c == '+' {
+                number.push(*c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let value = number.parse::<f64>().map_err(|_| GcodeError::Parse {
+            line: line_no,
+            message: format!("bad number after '{}': '{}'", letter, number),
+        })?;
+        // A long enough run of digits parses as infinity.
+        if !value.is_finite() {
+            return Err(GcodeError::Parse {
+                line: line_no,
+                message: format!("number after '{}' out of range", letter),
+            });
+        }
+        words.push((letter, value));
+    }
+    Ok(words)
+}
+
+// Far beyond any plotter's travel, but small enough that step counts and move times
+// computed from it stay in range.
+pub const MAX_COORDINATE: f64 = 1_000_000.0;
+
+// Parses one line. Returns `None` for blank and comment-only lines. A line with only
+// coordinates reuses the previous motion command, as most G-code generators expect.
+pub fn parse_line(
+    line: &str,
+    line_no: usize,
+    last_motion: Option<Command>,
+) -> Result<Option<Command>, GcodeError> {
+    let words = words(line, line_no)?;
+    let mut command = None;
+    let mut target = Target::default();
+    for (letter, value) in words {
+        match letter {
+            'N' => (),
+            'G' | 'M' => {
+                let code = format!("{}{}", letter, value);
+                let parsed = match code.as_str() {
+                    "G0" => Command::Rapid(Target::default()),
+                    "G1" => Command::Linear(Target::default()),
+                    "G28" => Command::Home,
+                    "M17" => Command::EnableMotors,
+                    "M18" | "M84" => Command::DisableMotors,
+                    _ => {
+                        return Err(GcodeError::Unsupported {
+                            line: line_no,
+                            word: code,
+                        })
+                    }
+                };
+                if command.is_some() {
+                    return Err(GcodeError::Parse {
+                        line: line_no,
+                        message: "more than one command on a line".to_string(),
+                    });
+                }
+                command = Some(parsed);
+            }
+            'X' | 'Y' if value.abs() > MAX_COORDINATE => {
+                return Err(GcodeError::Parse {
+                    line: line_no,
+                    message: format!("{} beyond {} mm", letter, MAX_COORDINATE),
+                });
+            }
+            'X' => target.x = Some(value),
+            'Y' => target.y = Some(value),
+            'F' => {
+                if value <= 0.0 {
+                    return Err(GcodeError::Parse {
+                        line: line_no,
+                        message: "feed rate must be positive".to_string(),
+                    });
+                }
+                target.f = Some(value)
+            }
+            _ => {
+                return Err(GcodeError::Unsupported {
+                    line: line_no,
+                    word: letter.to_string(),
+                })
+            }
+        }
+    }
+    let has_target = target != Target::default();
+    let command = match (command, last_motion) {
+        (Some(Command::Rapid(_)), _) => Some(Command::Rapid(target)),
+        (Some(Command::Linear(_)), _) => Some(Command::Linear(target)),
+        (Some(c), _) if has_target => {
+            return Err(GcodeError::Parse {
+                line: line_no,
+                message: format!("{:?} takes no coordinates", c),
+            })
+        }
+        (Some(c), _) => Some(c),
+        (None, Some(Command::Rapid(_))) if has_target => Some(Command::Rapid(target)),
+        (None, Some(Command::Linear(_))) if has_target => Some(Command::Linear(target)),
+        (None, _) if has_target => {
+            return Err(GcodeError::Parse {
+                line: line_no,
+                message: "coordinates without a motion command".to_string(),
+            })
+        }
+        (None, _) => None,
+    };
+    Ok(command)
+}
+
+pub struct PlotterConfig {
+    pub steps_per_mm: f64,
+    // Feed rates are in mm/min, as in G-code.
+    pub rapid_feed: f64,
+    pub default_feed: f64,
+    // The fastest the coils can be sequenced without the motor stalling.
+    pub min_step_interval: Duration,
+}
+
+impl Default for PlotterConfig {
+    fn default() -> Self {
+        PlotterConfig {
+            steps_per_mm: 40.0,
+            rapid_feed: 600.0,
+            default_feed: 300.0,
+            min_step_interval: Duration::from_millis(1),
+        }
+    }
+}
+
+// Drives an X/Y pair of steppers from G-code. There are no limit switches, so G28
+// returns to the position the axes were in when the plotter was created.
+pub struct Plotter {
+    motors: MultiStepper,
+    config: PlotterConfig,
+    feed: f64,
+}
+
+impl Plotter {
+    pub fn new(motors: MultiStepper, config: PlotterConfig) -> Self {
+        assert_eq!(motors.axes().len(), 2, "a plotter has X and Y axes");
+        let feed = config.default_feed;
+        Plotter {
+            motors,
+            config,
+            feed,
+        }
+    }
+
+    // Current position in mm.
+    pub fn position(&self) -> (f64, f64) {
+        let steps = self.motors.positions();
+        (
+            steps[0] as f64 / self.config.steps_per_mm,
+            steps[1] as f64 / self.config.steps_per_mm,
+        )
+    }
+
+    pub fn execute(&mut self, command: &Command) {
+        match command {
+            Command::Rapid(target) => {
+                let feed = self.config.rapid_feed;
+                self.move_to(target, feed);
+            }
+            Command::Linear(target) => {
+                if let Some(f) = target.f {
+                    self.feed = f;
+                }
+                let feed = self.feed;
+                self.move_to(target, feed);
+            }
+            Command::Home => {
+                let feed = self.config.rapid_feed;
+                self.move_to(
+                    &Target {
+                        x: Some(0.0),
+                        y: Some(0.0),
+                        f: None,
+                    },
+                    feed,
+                );
+            }
+            Command::EnableMotors => self.motors.enable(),
+            Command::DisableMotors => self.motors.disable(),
+        }
+    }
+
+    pub fn run<R: BufRead>(&mut self, reader: R) -> Result<(), GcodeError> {
+        let mut last_motion = None;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if let Some(command) = parse_line(&line, i + 1, last_motion)? {
+                if let Command::Rapid(_) | Command::Linear(_) = command {
+                    last_motion = Some(command);
+                }
+                self.execute(&command);
+            }
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, target: &Target, feed: f64) {
+        let (x, y) = self.position();
+        let x = target.x.unwrap_or(x);
+        let y = target.y.unwrap_or(y);
+        let targets = [
+            (x * self.config.steps_per_mm).round() as i64,
+            (y * self.config.steps_per_mm).round() as i64,
+        ];
+        let current = self.motors.positions();
+        let distance = ((targets[0] - current[0]) as f64).hypot((targets[1] - current[1]) as f64)
+            / self.config.steps_per_mm;
+        let major = (targets[0] - current[0])
+            .unsigned_abs()
+            .max((targets[1] - current[1]).unsigned_abs());
+        // A vanishingly small feed rate asks for a move that outlasts `Duration`.
+        let requested =
+            Duration::try_from_secs_f64(distance / (feed / 60.0)).unwrap_or(Duration::MAX);
+        let fastest = u32::try_from(major)
+            .ok()
+            .and_then(|major| self.config.min_step_interval.checked_mul(major))
+            .unwrap_or(Duration::MAX);
+        self.motors.move_to(&targets, requested.max(fastest));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(x: Option<f64>, y: Option<f64>, f: Option<f64>) -> Target {
+        Target { x, y, f }
+    }
+
+    #[test]
+    fn parses_motion_commands() {
+        assert_eq!(
+            parse_line("G0 X10 Y-2.5", 1, None).unwrap(),
+            Some(Command::Rapid(target(Some(10.0), Some(-2.5), None)))
+        );
+        assert_eq!(
+            parse_line("g1 x1.5 f300", 1, None).unwrap(),
+            Some(Command::Linear(target(Some(1.5), None, Some(300.0))))
+        );
+        assert_eq!(
+            parse_line("N10 G01 Y+3", 1, None).unwrap(),
+            Some(Command::Linear(target(None, Some(3.0), None)))
+        );
+    }
+
+    #[test]
+    fn parses_machine_commands() {
+        assert_eq!(parse_line("G28", 1, None).unwrap(), Some(Command::Home));
+        assert_eq!(
+            parse_line("M17", 1, None).unwrap(),
+            Some(Command::EnableMotors)
+        );
+        assert_eq!(
+            parse_line("M18", 1, None).unwrap(),
+            Some(Command::DisableMotors)
+        );
+        assert_eq!(
+            parse_line("M84", 1, None).unwrap(),
+            Some(Command::DisableMotors)
+        );
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        assert_eq!(parse_line("", 1, None).unwrap(), None);
+        assert_eq!(parse_line("   ; just a comment", 1, None).unwrap(), None);
+        assert_eq!(parse_line("(pen up)", 1, None).unwrap(), None);
+        assert_eq!(
+            parse_line("G1 (move) X2 ; to the right", 1, None).unwrap(),
+            Some(Command::Linear(target(Some(2.0), None, None)))
+        );
+    }
+
+    #[test]
+    fn coordinates_alone_reuse_the_last_motion() {
+        let last = Some(Command::Rapid(Target::default()));
+        assert_eq!(
+            parse_line("X4 Y5", 1, last).unwrap(),
+            Some(Command::Rapid(target(Some(4.0), Some(5.0), None)))
+        );
+        assert!(matches!(
+            parse_line("X4", 3, None),
+            Err(GcodeError::Parse { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(
+            parse_line("G2 X1 Y1", 7, None),
+            Err(GcodeError::Unsupported { line: 7, ref word }) if word == "G2"
+        ));
+        assert!(matches!(
+            parse_line("G1 Z1", 1, None),
+            Err(GcodeError::Unsupported { ref word, .. }) if word == "Z"
+        ));
+        assert!(matches!(
+            parse_line("G0 G1", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line("G1 X1 F0", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line("G28 X1", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line("G1 X", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line("1 G1", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line("G1 X1000001", 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(matches!(
+            parse_line(&format!("G1 F1{}", "0".repeat(400)), 1, None),
+            Err(GcodeError::Parse { .. })
+        ));
+        assert!(parse_line("G1 X-1000000 Y1000000", 1, None).is_ok());
+    }
+}
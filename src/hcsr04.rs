@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level, OutputPin};
+
+use crate::temperature::Temperature;
+use crate::timing::{self, Realtime};
+
+// The datasheet's working range, in metres.
+const MIN_DISTANCE: f64 = 0.02;
+const MAX_DISTANCE: f64 = 4.0;
+// With nothing in range the module only ends the echo after about 38 ms. Waiting
+// that long would spin under SCHED_FIFO for nothing, so the wait stops this long
+// after an echo from MAX_DISTANCE would have ended.
+const ECHO_MARGIN: Duration = Duration::from_millis(1);
+// Lets the previous burst die down before the next trigger.
+const MIN_CYCLE: Duration = Duration::from_millis(60);
+
+// Speed of sound in dry air, in m/s.
+pub fn speed_of_sound(temperature: Temperature) -> f64 {
+    331.3 * (temperature.kelvin() / 273.15).sqrt()
+}
+
+// The longest echo worth waiting for at `temperature`.
+fn echo_timeout(temperature: Temperature) -> Duration {
+    Duration::from_secs_f64(2.0 * MAX_DISTANCE / speed_of_sound(temperature)) + ECHO_MARGIN
+}
+
+#[derive(Debug)]
+pub enum HcSr04Error {
+    GpioError(rppal::gpio::Error),
+    // The echo never started, usually a wiring problem.
+    TimeOut,
+    // Nothing within range, or the echo came back too soon to be real. Distance in metres.
+    OutOfRange(Option<f64>),
+}
+
+impl fmt::Display for HcSr04Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HcSr04Error::GpioError(e) => write!(f, "gpio error: {}", e),
+            HcSr04Error::TimeOut => write!(f, "no echo from the sensor"),
+            HcSr04Error::OutOfRange(Some(d)) => write!(f, "{:.3} m is out of range", d),
+            HcSr04Error::OutOfRange(None) => write!(f, "nothing in range"),
+        }
+    }
+}
+
+impl Error for HcSr04Error {}
+
+impl From<rppal::gpio::Error> for HcSr04Error {
+    fn from(e: rppal::gpio::Error) -> HcSr04Error {
+        HcSr04Error::GpioError(e)
+    }
+}
+
+// Median of the last `window` values, which throws out the odd stray echo that an
+// average would smear into its neighbours.
+pub struct MedianFilter {
+    values: VecDeque<f64>,
+    window: usize,
+}
+
+impl MedianFilter {
+    pub fn new(window: usize) -> Self {
+        MedianFilter {
+            values: VecDeque::with_capacity(window),
+            window: window.max(1),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) -> f64 {
+        if self.values.len() == self.window {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+        self.median().unwrap_or(value)
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<f64> = self.values.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let mid = sorted.len() / 2;
+        Some(if sorted.len().is_multiple_of(2) {
+            (sorted[mid - 1] + sorted[mid]) / 2.0
+        } else {
+            sorted[mid]
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+pub struct HcSr04 {
+    trig: OutputPin,
+    echo: InputPin,
+    temperature: Temperature,
+    filter: Option<MedianFilter>,
+    realtime: Option<Realtime>,
+    last_trigger: Option<Instant>,
+}
+
+impl HcSr04 {
+    // ECHO is a 5 V output; put it through a divider before the Pi's pin.
+    pub fn new(trig: u8, echo: u8) -> Result<Self, rppal::gpio::Error> {
+        let gpio = Gpio::new()?;
+        Ok(HcSr04 {
+            trig: gpio.get(trig)?.into_output_low(),
+            echo: gpio.get(echo)?.into_input(),
+            temperature: Temperature::from_celsius(20.0),
+            filter: None,
+            realtime: None,
+            last_trigger: None,
+        })
+    }
+
+    // Passes every reading through a median filter of this many samples.
+    pub fn with_filter(mut self, window: usize) -> Self {
+        self.filter = Some(MedianFilter::new(window));
+        self
+    }
+
+    // Times the echo under SCHED_FIFO, see `timing::Realtime`.
+    pub fn with_realtime(mut self, realtime: Realtime) -> Self {
+        self.realtime = Some(realtime);
+        self
+    }
+
+    // Air temperature for the speed of sound, e.g. from a `Dht` or `Thermistor`.
+    // About 0.17 % of distance per °C.
+    pub fn set_temperature(&mut self, temperature: Temperature) {
+        self.temperature = temperature;
+    }
+
+    pub fn echo_time(&mut self) -> Result<Duration, HcSr04Error> {
+        if let Some(last) = self.last_trigger {
+            thread::sleep((last + MIN_CYCLE).saturating_duration_since(Instant::now()));
+        }
+        self.last_trigger = Some(Instant::now());
+
+        let _realtime = self.realtime.and_then(|r| r.enter().ok());
+        self.trig.set_high();
+        timing::delay_us(10);
+        self.trig.set_low();
+
+        let echo = &self.echo;
+        timing::wait_for_level(|| echo.read(), Level::High, Duration::from_millis(10))
+            .ok_or(HcSr04Error::TimeOut)?;
+        timing::wait_for_level(|| echo.read(), Level::Low, echo_timeout(self.temperature))
+            .ok_or(HcSr04Error::OutOfRange(None))
+    }
+
+    // Distance in metres, median-filtered if a filter is set.
+    pub fn distance(&mut self) -> Result<f64, HcSr04Error> {
+        let echo = self.echo_time()?;
+        // There and back.
+        let distance = echo.as_secs_f64() * speed_of_sound(self.temperature) / 2.0;
+        if !(MIN_DISTANCE..=MAX_DISTANCE).contains(&distance) {
+            return Err(HcSr04Error::OutOfRange(Some(distance)));
+        }
+        Ok(match &mut self.filter {
+            Some(filter) => filter.push(distance),
+            None => distance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_of_sound_rises_with_temperature() {
+        assert!((speed_of_sound(Temperature::from_celsius(0.0)) - 331.3).abs() < 1e-9);
+        let warm = speed_of_sound(Temperature::from_celsius(20.0));
+        assert!((warm - 343.2).abs() < 0.1, "{}", warm);
+        assert!(speed_of_sound(Temperature::from_celsius(-20.0)) < 331.3);
+    }
+
+    #[test]
+    fn echo_timeout_covers_the_range_only() {
+        let warm = echo_timeout(Temperature::from_celsius(20.0));
+        assert!(warm > Duration::from_micros(23_300), "{:?}", warm);
+        assert!(warm < Duration::from_millis(25), "{:?}", warm);
+        // Sound is slower in the cold, so the wait is longer.
+        assert!(echo_timeout(Temperature::from_celsius(-20.0)) > warm);
+    }
+
+    #[test]
+    fn median_filter_drops_a_stray_reading() {
+        let mut filter = MedianFilter::new(3);
+        assert_eq!(filter.median(), None);
+        assert_eq!(filter.push(1.0), 1.0);
+        assert_eq!(filter.push(1.2), 1.1);
+        assert_eq!(filter.push(3.9), 1.2);
+        assert_eq!(filter.push(1.1), 1.2);
+        // The window is full, so 1.0 is gone.
+        assert_eq!(filter.push(1.3), 1.3);
+        filter.clear();
+        assert_eq!(filter.median(), None);
+    }
+
+    #[test]
+    fn median_filter_window_of_zero_holds_one() {
+        let mut filter = MedianFilter::new(0);
+        assert_eq!(filter.push(2.0), 2.0);
+        assert_eq!(filter.push(5.0), 5.0);
+    }
+}
@@ -1,11 +1,37 @@
-use rppal::gpio::{Gpio, InputPin, Level, OutputPin};
-use std::collections::HashSet;
+use rppal::gpio::{Gpio, Level};
+use rppal::i2c::I2c;
+use rppal::spi::SlaveSelect;
 use std::error::Error;
-use std::f64::INFINITY;
+use std::io;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use crate::adc0834::{Adc0834, Channel};
+use crate::ads1115::{self, Ads1115, Gain};
+use crate::analog::AnalogInput;
+use crate::analog_pwm::{AnalogToPwm, Mapping};
+use crate::binary_sensor::{BinarySensor, BinarySensorConfig};
+use crate::button::{Button, ButtonConfig, ButtonEvent};
+use crate::dht::{Dht, Model as DhtModel};
+use crate::ds18b20::Ds18b20Bus;
+use crate::hcsr04::HcSr04;
+use crate::ir_remote::{ButtonMap, IrEvent, IrReceiver};
+use crate::joystick::{Calibration, Joystick};
+use crate::keypad::{KeyEvent, MatrixKeypad, KEYS_4X4};
+use crate::lcd1602::{self, Lcd1602};
+use crate::ldr::{LdrModel, LdrSensor};
+use crate::mcp3x08::{Mcp3x08, Model};
+use crate::output::pwm_channels;
+use crate::passcode::{CodeStore, PasscodeConfig, PasscodeEntry, PasscodeEvent};
+use crate::pir::{OccupancyEvent, PirConfig, PirSensor};
+use crate::pwm::PwmLed;
+use crate::rotary_encoder::{Acceleration, EncoderEvent, RotaryEncoder};
+use crate::temperature::Temperature;
+use crate::thermistor::{Model as ThermistorModel, Thermistor};
+use crate::timing::Realtime;
 
 const GPIO24: u8 = 24;
 const GPIO23: u8 = 23;
@@ -17,57 +43,57 @@ const GPIO17: u8 = 17;
 const GPIO25: u8 = 25;
 
 pub fn button() -> Result<(), Box<dyn Error>> {
-    let mut input = Gpio::new()?.get(GPIO18)?.into_input();
+    let events = Button::new(GPIO18, ButtonConfig::default())?.spawn();
     let mut output = Gpio::new()?.get(GPIO17)?.into_output();
 
     output.set_high();
-    input
-        .set_interrupt(rppal::gpio::Trigger::FallingEdge)
-        .expect("failed to set_interrupt.");
-    loop {
-        match input.poll_interrupt(true, None) {
-            Ok(_) => output.toggle(),
-            Err(e) => println!("{}", e),
+    for event in events {
+        println!("{:?}", event);
+        if event == ButtonEvent::Click {
+            output.toggle();
+        }
+    }
+    Ok(())
+}
+
+pub fn rotary_encoder() -> Result<(), Box<dyn Error>> {
+    let events = RotaryEncoder::new(GPIO17, GPIO18, Some(GPIO27))?
+        .with_acceleration(Acceleration::default())
+        .spawn();
+
+    let mut value: i64 = 0;
+    for event in events {
+        match event {
+            EncoderEvent::Increment { steps, .. } => value += steps as i64,
+            EncoderEvent::Decrement { steps, .. } => value -= steps as i64,
+            EncoderEvent::Button(ButtonEvent::Click) => value = 0,
+            EncoderEvent::Button(_) => continue,
         }
+        println!("{}", value);
     }
+    Ok(())
 }
 
 pub fn slide_button() -> Result<(), Box<dyn Error>> {
     let mut led_1 = Gpio::new()?.get(GPIO22)?.into_output();
     let mut led_2 = Gpio::new()?.get(GPIO27)?.into_output();
-    let mut input_pin = Gpio::new()?.get(GPIO17)?.into_input();
-
-    if input_pin.is_low() {
-        led_1.set_low();
-        led_2.set_high();
-    } else {
-        led_2.set_high();
-        led_1.set_high();
-    }
+    let mut switch = BinarySensor::new(GPIO17, BinarySensorConfig::slide_switch())?;
 
-    input_pin
-        .set_interrupt(rppal::gpio::Trigger::Both)
-        .expect("failed to set_interrupt.");
+    let mut show = |active: bool| {
+        led_1.write(if active { Level::High } else { Level::Low });
+        led_2.write(if active { Level::Low } else { Level::High });
+    };
+    show(switch.is_active());
 
-    loop {
-        match input_pin.poll_interrupt(true, None) {
-            Ok(_) => {
-                led_1.toggle();
-                led_2.toggle();
-            }
-            Err(e) => println!("{}", e),
-        }
-    }
+    let running = AtomicBool::new(true);
+    switch.run(&running, |change| show(change.active))?;
+    Ok(())
 }
 
 pub fn tilt() -> Result<(), Box<dyn Error>> {
     let mut led_1 = Gpio::new()?.get(GPIO22)?.into_output();
     let mut led_2 = Gpio::new()?.get(GPIO27)?.into_output();
-    let mut input_pin = Gpio::new()?.get(GPIO17)?.into_input();
-
-    input_pin
-        .set_interrupt(rppal::gpio::Trigger::Both)
-        .expect("failed to set_interrupt.");
+    let mut sensor = BinarySensor::new(GPIO17, BinarySensorConfig::tilt())?;
 
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -75,355 +101,355 @@ pub fn tilt() -> Result<(), Box<dyn Error>> {
         r.store(false, Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
-    while running.load(Ordering::SeqCst) {
-        match input_pin.poll_interrupt(true, None) {
-            Ok(trigger) => {
-                thread::sleep(Duration::from_millis(10));
-                match trigger {
-                    Some(rppal::gpio::Level::High) => {
-                        led_1.set_high();
-                        led_2.set_low();
-                    }
-                    Some(rppal::gpio::Level::Low) => {
-                        println!("Tilt!");
-                        led_2.set_high();
-                        led_1.set_low();
-                    }
-                    None => break,
-                }
-                thread::sleep(Duration::from_millis(500));
-            }
-            Err(_) => println!("\nEnd"),
+    sensor.run(&running, |change| {
+        if change.active {
+            println!("Tilt! (was level for {:?})", change.previous_duration);
+            led_2.set_high();
+            led_1.set_low();
+        } else {
+            led_1.set_high();
+            led_2.set_low();
         }
-    }
+    })?;
+    println!("\nEnd");
     led_1.set_low();
     led_2.set_low();
     Ok(())
 }
 
-fn snd_bit(clk_pin: &mut OutputPin, input_pin: &mut OutputPin, value: u8) {
-    clk_pin.set_low();
-    thread::sleep(Duration::from_micros(2));
-    if value == 0 {
-        input_pin.set_low();
-    } else {
-        input_pin.set_high();
+// The ADC environment variable picks the ADC; unset, it is the kit's ADC0834
+// (bit-banged). mcp3008 / mcp3208 go on CE0 of SPI0 with VREF at 3.3 V. ads1115 is
+// at 0x48 on I2C1, on the ±6.144 V range so it can read signals up to 5 V.
+fn analog_input() -> Result<Box<dyn AnalogInput>, Box<dyn Error>> {
+    let adc: Box<dyn AnalogInput> = match std::env::var("ADC").as_deref() {
+        Ok("ads1115") => {
+            let mut adc = Ads1115::new(I2c::new()?, ads1115::DEFAULT_ADDRESS);
+            adc.set_gain(Gain::Fsr6_144)?;
+            Box::new(adc)
+        }
+        Ok("mcp3008") => Box::new(Mcp3x08::new(Model::Mcp3008, SlaveSelect::Ss0, 3.3)?),
+        Ok("mcp3208") => Box::new(Mcp3x08::new(Model::Mcp3208, SlaveSelect::Ss0, 3.3)?),
+        _ => Box::new(adc0834()?),
+    };
+    Ok(adc)
+}
+
+// The kit's ADC0834, bit-banged, or with ADC=adc0834-spi on SPI0 with CS on CE0.
+fn adc0834() -> Result<Adc0834, Box<dyn Error>> {
+    let adc = match std::env::var("ADC").as_deref() {
+        Ok("adc0834-spi") => Adc0834::with_spi(SlaveSelect::Ss0)?,
+        _ => Adc0834::new(GPIO17, GPIO23, GPIO27, GPIO18)?,
+    };
+    // VREF is tied to VCC on the kit.
+    Ok(adc.with_reference(5.0))
+}
+
+// Supply voltage of the divider: 3.3 V for the MCP3x08, the same as its VREF, and
+// 5 V for the others. The ADS1115 measures against its internal reference, so the
+// supply has to be given apart from the ADC's reference voltage.
+fn divider_supply() -> f64 {
+    match std::env::var("ADC").as_deref() {
+        Ok("mcp3008") | Ok("mcp3208") => 3.3,
+        _ => 5.0,
     }
-    clk_pin.set_high();
-    thread::sleep(Duration::from_micros(2));
 }
 
-fn rcv_bit(clk_pin: &mut OutputPin, output_pin: &mut InputPin) -> u8 {
-    let result;
-    clk_pin.set_low();
-    thread::sleep(Duration::from_micros(2));
-    if output_pin.is_high() {
-        result = 1;
-    } else {
-        result = 0;
+// With LCD set in the environment, readings also go to an LCD1602 at 0x27 on I2C1.
+fn lcd() -> Result<Option<Lcd1602<I2c>>, Box<dyn Error>> {
+    if std::env::var_os("LCD").is_none() {
+        return Ok(None);
+    }
+    let mut lcd = Lcd1602::new(I2c::new()?, lcd1602::DEFAULT_ADDRESS)?;
+    for (slot, glyph) in lcd1602::BAR_GLYPHS.iter().enumerate() {
+        lcd.define_glyph(slot as u8, glyph)?;
     }
-    clk_pin.set_high();
-    thread::sleep(Duration::from_micros(2));
-    return result;
+    Ok(Some(lcd))
 }
 
 pub fn potentiometer() -> Result<(), Box<dyn Error>> {
-    let mut msb = 0;
-    let mut lsb = 0;
+    let adc = analog_input()?;
+    let led = PwmLed::new(Gpio::new()?.get(GPIO22)?.into_output());
+    let mapping = Mapping::Linear {
+        out_min: 0.0,
+        out_max: 1.0,
+    };
 
-    let mut adc_cs = Gpio::new()?.get(GPIO17)?.into_output();
-    let mut adc_do = Gpio::new()?.get(GPIO23)?.into_input();
-    let mut adc_di = Gpio::new()?.get(GPIO27)?.into_output();
-    let mut adc_clk = Gpio::new()?.get(GPIO18)?.into_output();
-    let mut led = Gpio::new()?.get(GPIO22)?.into_output();
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+    // The kit wires the potentiometer's wiper to CH1.
+    AnalogToPwm::new(adc, 1, led, mapping).run(&running)
+}
 
+// A bridge sensor's outputs on CH0 (+) and CH1 (-), read as a differential pair.
+pub fn bridge() -> Result<(), Box<dyn Error>> {
+    let mut adc = adc0834()?;
     loop {
-        // 変換の開始
-        adc_cs.set_low();
-        // スタートビット
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-        // SGL
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-        // ODD
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-        // Select
-        snd_bit(&mut adc_clk, &mut adc_di, 0);
-
-        // スカされるbit。送信する値に意味なし。
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-
-        // MSB-First Data
-        for _ in 0..7 {
-            msb = msb << 1 | rcv_bit(&mut adc_clk, &mut adc_do);
-        }
-        // MSB-First DataとLSB-First Dataの最下位bit
-        adc_clk.set_low();
-        thread::sleep(Duration::from_micros(2));
-        msb = msb << 1 | if adc_do.is_high() { 1 } else { 0 };
-        lsb = if adc_do.is_high() { 1 } else { 0 };
-        adc_clk.set_high();
-        // LSB-First Data
-        for i in 1..8 {
-            lsb = rcv_bit(&mut adc_clk, &mut adc_do) << i | lsb;
-        }
-        // 変換の終了
-        adc_cs.set_high();
-        // LED点灯
-        if msb == lsb {
-            led.set_pwm_frequency(2000.0, (msb as f64) / 255.0)?;
-        }
+        // A pair reads zero when its - input is higher, so read it both ways round.
+        let up = adc.get_adc_result(Channel::Diff01)?;
+        let down = adc.get_adc_result(Channel::Diff10)?;
+        let volts = (up as f64 - down as f64) * adc.reference_voltage() / 255.0;
+        println!("{:+8.1} mV", volts * 1000.0);
+        thread::sleep(Duration::from_millis(200));
     }
 }
 
 pub fn keypad() -> Result<(), Box<dyn Error>> {
-    const KEYS: [char; 16] = [
-        '1', '2', '3', 'A', '4', '5', '6', 'B', '7', '8', '9', 'C', '*', '0', '#', 'D',
-    ];
     let row = [GPIO18, GPIO23, GPIO24, GPIO25];
     let col = [SPIMOSI, GPIO22, GPIO27, GPIO17];
-    let mut row_pins = row
-        .map(|pin| -> Result<OutputPin, Box<dyn Error>> {
-            Ok(Gpio::new()?.get(pin)?.into_output())
-        })
-        .map(|result: Result<OutputPin, Box<dyn Error>>| result.unwrap());
-    let col_pins = col
-        .map(|pin| -> Result<InputPin, Box<dyn Error>> { Ok(Gpio::new()?.get(pin)?.into_input()) })
-        .map(|result: Result<InputPin, Box<dyn Error>>| result.unwrap());
-
-    let mut pressed: HashSet<char> = vec![].into_iter().collect();
-    let mut last_pressed: HashSet<char> = vec![].into_iter().collect();
-    loop {
-        for (i, output) in &mut row_pins.iter_mut().enumerate() {
-            output.set_high();
-            for (j, input) in col_pins.iter().enumerate() {
-                if input.is_high() {
-                    pressed.insert(KEYS[i * 4 + j]);
-                }
-            }
-            thread::sleep(Duration::from_millis(1));
-            output.set_low();
-        }
-        if pressed.difference(&last_pressed).collect::<Vec<_>>().len() != 0 {
-            println!("{:?}", pressed.difference(&last_pressed));
+    let started = Instant::now();
+
+    for event in MatrixKeypad::new(&row, &col, &KEYS_4X4)?.spawn() {
+        match event {
+            KeyEvent::KeyDown { key, at } => println!("{:>8.3?} down {}", at - started, key),
+            KeyEvent::KeyUp { key, at } => println!("{:>8.3?} up   {}", at - started, key),
+            KeyEvent::Ghosting { .. } => println!("too many keys held"),
         }
-        last_pressed.clone_from(&pressed);
-        pressed.clear();
-        thread::sleep(Duration::from_millis(100));
     }
+    Ok(())
 }
 
-struct Adc0834 {
-    adc_cs: u8,
-    adc_do: u8,
-    adc_di: u8,
-    adc_clk: u8,
-}
+pub fn door_lock() -> Result<(), Box<dyn Error>> {
+    // One `name:salt:hash` line per code, as made by `CodeStore::entry`.
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "passcodes.txt".to_string());
+    let mut entry = PasscodeEntry::new(CodeStore::load(path)?, PasscodeConfig::default());
+    let row = [GPIO18, GPIO23, GPIO24, GPIO25];
+    let col = [SPIMOSI, GPIO22, GPIO27, GPIO17];
+    let keys = MatrixKeypad::new(&row, &col, &KEYS_4X4)?.spawn();
 
-impl Adc0834 {
-    fn new(adc_cs: u8, adc_do: u8, adc_di: u8, adc_clk: u8) -> Self {
-        Self {
-            adc_cs,
-            adc_do,
-            adc_di,
-            adc_clk,
-        }
-    }
-    fn get_adc_result(&self, ch_pin: u8) -> Result<u8, Box<dyn Error>> {
-        let mut adc_cs = Gpio::new()?.get(self.adc_cs)?.into_output();
-        let mut adc_do = Gpio::new()?.get(self.adc_do)?.into_input();
-        let mut adc_di = Gpio::new()?.get(self.adc_di)?.into_output();
-        let mut adc_clk = Gpio::new()?.get(self.adc_clk)?.into_output();
-
-        // 変換の開始
-        adc_cs.set_low();
-        // スタートビット
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-        // SGL
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-        // ODD
-        snd_bit(&mut adc_clk, &mut adc_di, ch_pin & 1 as u8);
-        // Select
-        snd_bit(&mut adc_clk, &mut adc_di, if ch_pin > 1 { 1 } else { 0 });
-
-        // スカされるクロック。送信する値に意味なし。
-        snd_bit(&mut adc_clk, &mut adc_di, 1);
-
-        let mut msb = 0;
-        let mut lsb;
-        // MSB-First Data
-        for _ in 0..7 {
-            msb = msb << 1 | rcv_bit(&mut adc_clk, &mut adc_do);
-        }
-        // MSB-First DataとLSB-First Dataの最下位bit
-        adc_clk.set_low();
-        thread::sleep(Duration::from_micros(2));
-        msb = msb << 1 | if adc_do.is_high() { 1 as u8 } else { 0 as u8 };
-        lsb = if adc_do.is_high() { 1 } else { 0 };
-        adc_clk.set_high();
-        // LSB-First Data
-        for i in 1..8 {
-            lsb = rcv_bit(&mut adc_clk, &mut adc_do) << i | lsb;
-        }
-        // 変換の終了
-        adc_cs.set_high();
-        if lsb == msb {
-            Ok(lsb)
-        } else {
-            Ok(0)
+    loop {
+        let events = match keys.recv_timeout(Duration::from_millis(100)) {
+            Ok(KeyEvent::KeyDown { key, at }) => entry.key(key, at),
+            Ok(_) => vec![],
+            Err(RecvTimeoutError::Timeout) => entry.tick(Instant::now()),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        for event in events {
+            match event {
+                PasscodeEvent::Digit(n) => println!("{}", "*".repeat(n)),
+                event => println!("{:?}", event),
+            }
         }
     }
+    Ok(())
 }
 
 pub fn joystick() -> Result<(), Box<dyn Error>> {
-    let button = Gpio::new()?.get(GPIO22)?.into_input_pullup();
-
-    let mut x_val;
-    let mut y_val;
-    let adc = Adc0834::new(GPIO17, GPIO23, GPIO27, GPIO18);
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "joystick.cal".to_string());
+    let mut stick = Joystick::new(analog_input()?, 0, 1, GPIO22)?;
+    // Calibrate only when there is no file yet; a corrupt one is reported, not replaced.
+    match Calibration::load(&path) {
+        Ok(calibration) => stick = stick.with_calibration(calibration),
+        Err(e)
+            if e.downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+        {
+            stick.calibrate_interactive()?.save(&path)?;
+            println!("saved calibration to {}", path);
+        }
+        Err(e) => return Err(format!("{}: {}", path, e).into()),
+    }
 
     loop {
-        x_val = adc.get_adc_result(0)?;
-        y_val = adc.get_adc_result(1)?;
-        println!(
-            "x: {}, y: {}, button: {}",
-            x_val,
-            y_val,
-            if button.is_low() {
-                "pressed"
-            } else {
-                "not pressed"
-            }
-        );
-        thread::sleep(Duration::from_millis(100));
+        let events = stick.poll()?;
+        let (x, y) = stick.position();
+        for event in events {
+            println!("{:?} x: {:+.2}, y: {:+.2}", event, x, y);
+        }
+        thread::sleep(Duration::from_millis(20));
     }
 }
 
 pub fn photoregister() -> Result<(), Box<dyn Error>> {
-    let mut val;
-
-    let adc = Adc0834::new(GPIO17, GPIO23, GPIO27, GPIO18);
-    let mut led = Gpio::new()?.get(GPIO22)?.into_output();
+    let mut ldr = LdrSensor::new(analog_input()?, 0, LdrModel::gl5528(), divider_supply());
+    let mut led = PwmLed::new(Gpio::new()?.get(GPIO22)?.into_output());
 
-    loop {
-        val = adc.get_adc_result(0)?;
-        led.set_pwm_frequency(2000.0, (val as f64) / 255.0)?;
-        println!("val: {}", val);
-        thread::sleep(Duration::from_millis(100));
-    }
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+    let mut result = Ok(());
+    ldr.run(&running, Duration::from_millis(100), |lux, event| {
+        // 1 lux to 1000 lux spread evenly over the brightness, as the eye sees it.
+        result = led.set_brightness(lux.max(1.0).log10() / 3.0);
+        match event {
+            Some(event) => println!("{:?} at {:.1} lux", event.level, lux),
+            None => println!("{:.1} lux", lux),
+        }
+        if result.is_err() {
+            running.store(false, Ordering::SeqCst);
+        }
+    })?;
+    result
 }
 
 pub fn thermistor() -> Result<(), Box<dyn Error>> {
-    use num::Float;
-
-    let mut analog_val: u8;
-    let mut vr: f64;
-    let mut rt: f64;
-    let mut temp: f64;
-    let mut cel: f64;
-    let mut fah: f64;
-    let mut last_cel: f64 = INFINITY;
-
-    let adc = Adc0834::new(GPIO17, GPIO23, GPIO27, GPIO18);
+    // THERMISTOR=100k for an NTC 100k probe with a 100 kΩ series resistor.
+    let (model, series) = match std::env::var("THERMISTOR").as_deref() {
+        Ok("100k") => (ThermistorModel::ntc_100k(), 100_000.0),
+        _ => (ThermistorModel::ntc_10k(), 10_000.0),
+    };
+    let mut thermistor = Thermistor::new(analog_input()?, 0, model, divider_supply())
+        .with_series_resistor(series)
+        .with_samples(8);
+    let mut lcd = lcd()?;
+    let mut last: Option<Temperature> = None;
 
     loop {
-        analog_val = adc.get_adc_result(0).expect("adc failed");
-        vr = 5.0 * analog_val as f64 / 255.0;
-        rt = 10000.0 * vr / (5.0 - vr);
-        temp = 1.0 / ((Float::ln(rt / 10000.0) / 3950.0) + (1.0 / (273.15 + 25.0)));
-        cel = temp - 273.15;
-        fah = cel * 1.8 + 32.0;
-        if last_cel != cel {
-            println!("cel: {}, fah: {}", cel, fah);
+        match thermistor.read() {
+            Ok(temp) => {
+                if last.is_none_or(|l| (l.celsius() - temp.celsius()).abs() >= 0.1) {
+                    println!("cel: {:.1}, fah: {:.1}", temp.celsius(), temp.fahrenheit());
+                    if let Some(lcd) = &mut lcd {
+                        lcd.write_line(0, &format!("{:.1}°C", temp.celsius()))?;
+                        lcd.write_line(1, &format!("{:.1}°F", temp.fahrenheit()))?;
+                    }
+                    last = Some(temp);
+                }
+            }
+            Err(e) => {
+                println!("{}", e);
+                if let Some(lcd) = &mut lcd {
+                    lcd.write_line(0, "Error")?;
+                    lcd.write_line(1, &e.to_string())?;
+                }
+                last = None;
+            }
         }
-        last_cel = cel;
         thread::sleep(Duration::from_millis(100));
     }
 }
 
-struct Dht11 {
-    pin: u8,
-}
-
-#[derive(Debug)]
-enum Dth11Error {
-    GpioError(rppal::gpio::Error),
-    TimeOut,
-    CheckSum,
-}
-
-impl From<rppal::gpio::Error> for Dth11Error {
-    fn from(e: rppal::gpio::Error) -> Dth11Error {
-        Dth11Error::GpioError(e)
+pub fn dht() -> Result<(), Box<dyn Error>> {
+    // DHT=22 for a DHT22/AM2302.
+    let model = match std::env::var("DHT").as_deref() {
+        Ok("22") => DhtModel::Dht22,
+        _ => DhtModel::Dht11,
+    };
+    let mut dht = Dht::new(GPIO17, model).with_realtime(Realtime::default());
+    let mut lcd = lcd()?;
+    loop {
+        match dht.read() {
+            Ok(reading) => {
+                println!(
+                    "h: {:.1}%  t: {:.1}*c",
+                    reading.humidity,
+                    reading.temperature.celsius()
+                );
+                if let Some(lcd) = &mut lcd {
+                    let temp = format!("Temp {:.1}°C", reading.temperature.celsius());
+                    let humidity = format!(
+                        "{:>3.0}% {}",
+                        reading.humidity,
+                        lcd1602::bar_graph(reading.humidity / 100.0, 11, 0)
+                    );
+                    lcd.write_line(0, &temp)?;
+                    lcd.write_line(1, &humidity)?;
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+        thread::sleep(Duration::from_secs(2));
     }
 }
 
-impl Dht11 {
-    pub fn new(pin: u8) -> Self {
-        Dht11 { pin }
-    }
-
-    pub fn read(&self) -> Result<((u8, u8), (u8, u8)), Dth11Error> {
-        // send init request
-        {
-            let mut output = Gpio::new()?.get(self.pin)?.into_output();
-            output.set_low();
-            thread::sleep(Duration::from_millis(18));
-            output.set_high();
-            thread::sleep(Duration::from_nanos(40));
-        }
-        // get data from sensor
-        let mut bytes = [0u8; 5];
-        {
-            let input = Gpio::new()?.get(self.pin)?.into_input();
-            self.wait_level(&input, Level::High);
-            self.wait_level(&input, Level::Low);
-            self.wait_level(&input, Level::High);
-            for b in bytes.iter_mut() {
-                for _ in 0..8 {
-                    *b <<= 1;
-                    self.wait_level(&input, Level::Low);
-                    let dur = self.wait_level(&input, Level::High)?;
-                    if dur > 16 {
-                        *b |= 1;
-                    }
-                }
+pub fn ds18b20() -> Result<(), Box<dyn Error>> {
+    // Names for the probes, one `<rom id> <name>` per line.
+    let bus = match std::env::args().nth(1) {
+        Some(path) => Ds18b20Bus::new().load_names(path)?,
+        None => Ds18b20Bus::new(),
+    };
+    loop {
+        for reading in bus.read_all()? {
+            let name = reading.name.as_deref().unwrap_or(&reading.rom_id);
+            match reading.temperature {
+                Ok(t) => println!("{}: {:.2}*c", name, t.celsius()),
+                Err(e) => println!("{}: {}", name, e),
             }
         }
+        thread::sleep(Duration::from_secs(2));
+    }
+}
 
-        let sum: u16 = bytes.iter().take(4).map(|b| *b as u16).sum();
-        if bytes[4] as u16 == sum & 0x00FF {
-            return Ok(((bytes[0], bytes[1]), (bytes[2], bytes[3])));
-        } else {
-            return Err(Dth11Error::CheckSum);
+pub fn ir_remote() -> Result<(), Box<dyn Error>> {
+    // One `<name> <address> <command>` per line for the remote in use.
+    let names = match std::env::args().nth(1) {
+        Some(path) => Some(ButtonMap::load(path)?),
+        None => None,
+    };
+    let receiver = IrReceiver::new(GPIO17)?.with_realtime(Realtime::default());
+    for event in receiver.spawn() {
+        let (address, command, repeat) = match event {
+            IrEvent::Pressed {
+                address, command, ..
+            } => (address, command, ""),
+            IrEvent::Repeat {
+                address, command, ..
+            } => (address, command, " (repeat)"),
+        };
+        match names.as_ref().and_then(|n| n.name(address, command)) {
+            Some(name) => println!("{}{}", name, repeat),
+            None => println!(
+                "address: {:#04x}, command: {:#04x}{}",
+                address, command, repeat
+            ),
         }
     }
+    Ok(())
+}
 
-    fn wait_level(&self, input_pin: &InputPin, level: Level) -> Result<u8, Dth11Error> {
-        for i in 0u8..255 {
-            if input_pin.read() == level {
-                return Ok(i);
-            }
-            thread::sleep(Duration::from_micros(1));
+pub fn ultrasonic() -> Result<(), Box<dyn Error>> {
+    let mut sensor = HcSr04::new(GPIO23, GPIO24)?.with_filter(5);
+    // With DHT=11 or DHT=22, corrects the speed of sound from the sensor on GPIO17.
+    // A single attempt, so a flaky read only costs the correction.
+    let model = match std::env::var("DHT").as_deref() {
+        Ok("11") => Some(DhtModel::Dht11),
+        Ok("22") => Some(DhtModel::Dht22),
+        _ => None,
+    };
+    if let Some(model) = model {
+        match Dht::new(GPIO17, model).with_retries(0).read() {
+            Ok(reading) => sensor.set_temperature(reading.temperature),
+            Err(e) => eprintln!("no temperature correction: {}", e),
         }
-        Err(Dth11Error::TimeOut)
     }
-}
 
-pub fn dht() -> Result<(), Box<dyn Error>> {
-    let mut dht11 = Dht11::new(GPIO17);
     loop {
-        let ((h1, h2), (t1, t2)) = dht11.read().unwrap();
-        println!("h: {}.{}%  t: {}.{}*c", h1, h2, t1, t2);
+        match sensor.distance() {
+            // Parking gauge: the bar shrinks as the car gets within a metre.
+            Ok(d) => println!(
+                "{:>6.1} cm {}",
+                d * 100.0,
+                "#".repeat((d.min(1.0) * 40.0) as usize)
+            ),
+            Err(e) => println!("{}", e),
+        }
+        thread::sleep(Duration::from_millis(100));
     }
 }
 
 pub fn pir() -> Result<(), Box<dyn Error>> {
-    let mut pir = Gpio::new()?.get(GPIO17)?.into_input();
-    let mut red = Gpio::new()?.get(GPIO18)?.into_output();
-    let mut blue = Gpio::new()?.get(GPIO22)?.into_output();
-    let mut green = Gpio::new()?.get(GPIO27)?.into_output();
-
-    pir.set_interrupt(rppal::gpio::Trigger::Both)?;
+    let mut pir = PirSensor::new(GPIO17, PirConfig::default())?;
+    let mut leds: Vec<_> = pwm_channels(&[GPIO18, GPIO27, GPIO22], 100.0)?
+        .into_iter()
+        .map(PwmLed::new)
+        .collect();
+    // Red and green together for occupied, blue for vacant.
+    let mut show = |occupied: bool| -> Result<(), Box<dyn Error>> {
+        let on = if occupied { 1.0 } else { 0.0 };
+        leds[0].set_brightness(on)?;
+        leds[1].set_brightness(on)?;
+        leds[2].set_brightness(1.0 - on)
+    };
 
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -431,23 +457,22 @@ pub fn pir() -> Result<(), Box<dyn Error>> {
         r.store(false, Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
-    while running.load(Ordering::SeqCst) {
-        match pir.poll_interrupt(true, None) {
-            Ok(trigger) => match trigger {
-                Some(rppal::gpio::Level::High) => {
-                    red.set_pwm_frequency(100.0, 100.0)?;
-                    green.set_pwm_frequency(100.0, 100.0)?;
-                    blue.set_pwm_frequency(0.0, 100.0)?;
-                }
-                Some(rppal::gpio::Level::Low) => {
-                    red.set_pwm_frequency(0.0, 100.0)?;
-                    green.set_pwm_frequency(0.0, 100.0)?;
-                    blue.set_pwm_frequency(100.0, 100.0)?;
+    println!("warming up");
+    let mut result = show(false);
+    pir.run(&running, |event, counts| {
+        println!("{:?}", event);
+        result = match event {
+            OccupancyEvent::Occupied { .. } => show(true),
+            OccupancyEvent::Vacant { .. } => {
+                for (hour, count) in counts.counts() {
+                    println!("{}: {}", hour.format("%m-%d %H:00"), count);
                 }
-                None => (),
-            },
-            _ => break,
+                show(false)
+            }
+        };
+        if result.is_err() {
+            running.store(false, Ordering::SeqCst);
         }
-    }
-    Ok(())
+    })?;
+    result
 }
@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level, Trigger};
+
+use crate::button;
+use crate::timing::Realtime;
+
+// NEC timings. The receiver module demodulates the 38 kHz carrier and pulls its
+// output low during a burst ("mark").
+const LEADER_MARK: Duration = Duration::from_micros(9000);
+const LEADER_SPACE: Duration = Duration::from_micros(4500);
+const REPEAT_SPACE: Duration = Duration::from_micros(2250);
+const BIT_MARK: Duration = Duration::from_micros(562);
+const ZERO_SPACE: Duration = Duration::from_micros(562);
+const ONE_SPACE: Duration = Duration::from_micros(1687);
+// Repeat codes follow every 108 ms while a key is held.
+const REPEAT_WINDOW: Duration = Duration::from_millis(200);
+
+// Within 30 %, which covers cheap remotes and interrupt latency.
+fn near(duration: Duration, expected: Duration) -> bool {
+    let tolerance = expected.mul_f64(0.3);
+    duration + tolerance >= expected && duration <= expected + tolerance
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrEvent {
+    // `address` is 8 bits unless the remote uses extended NEC addressing.
+    Pressed {
+        address: u16,
+        command: u8,
+        at: Instant,
+    },
+    // The key of the last frame is still held.
+    Repeat {
+        address: u16,
+        command: u8,
+        at: Instant,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    LeaderMark,
+    LeaderSpace,
+    BitMark { bits: u32, count: u8 },
+    BitSpace { bits: u32, count: u8 },
+}
+
+// Turns receiver edges into NEC frames. Feed it the level the line changed to and
+// when; anything that does not fit the protocol puts it back to waiting for a leader.
+pub struct NecDecoder {
+    state: State,
+    last_edge: Option<Instant>,
+    last_code: Option<(u16, u8, Instant)>,
+}
+
+impl NecDecoder {
+    pub fn new() -> Self {
+        NecDecoder {
+            state: State::Idle,
+            last_edge: None,
+            last_code: None,
+        }
+    }
+
+    pub fn edge(&mut self, level: Level, at: Instant) -> Option<IrEvent> {
+        // How long the line stayed at the level it just left.
+        let width = self
+            .last_edge
+            .map(|last| at.saturating_duration_since(last));
+        self.last_edge = Some(at);
+        let (state, event) = match (self.state, level, width) {
+            // The first burst after a gap, which may be a leader.
+            (State::Idle, Level::Low, _) => (State::LeaderMark, None),
+            (State::LeaderMark, Level::High, Some(w)) if near(w, LEADER_MARK) => {
+                (State::LeaderSpace, None)
+            }
+            (State::LeaderSpace, Level::Low, Some(w)) if near(w, LEADER_SPACE) => {
+                (State::BitMark { bits: 0, count: 0 }, None)
+            }
+            (State::LeaderSpace, Level::Low, Some(w)) if near(w, REPEAT_SPACE) => {
+                (State::Idle, self.repeat(at))
+            }
+            (State::BitMark { bits, count }, Level::High, Some(w)) if near(w, BIT_MARK) => {
+                (State::BitSpace { bits, count }, None)
+            }
+            (State::BitSpace { bits, count }, Level::Low, Some(w)) => {
+                let bit = if near(w, ONE_SPACE) {
+                    1
+                } else if near(w, ZERO_SPACE) {
+                    0
+                } else {
+                    return self.restart(level);
+                };
+                // Least significant bit first.
+                let bits = bits | bit << count;
+                if count == 31 {
+                    (State::Idle, self.frame(bits, at))
+                } else {
+                    (
+                        State::BitMark {
+                            bits,
+                            count: count + 1,
+                        },
+                        None,
+                    )
+                }
+            }
+            _ => return self.restart(level),
+        };
+        self.state = state;
+        event
+    }
+
+    fn restart(&mut self, level: Level) -> Option<IrEvent> {
+        // A burst out of step may still be the start of a new leader.
+        self.state = if level == Level::Low {
+            State::LeaderMark
+        } else {
+            State::Idle
+        };
+        None
+    }
+
+    fn frame(&mut self, bits: u32, at: Instant) -> Option<IrEvent> {
+        let [address, address_inv, command, command_inv] = bits.to_le_bytes();
+        if command != !command_inv {
+            return None;
+        }
+        let address = if address == !address_inv {
+            address as u16
+        } else {
+            u16::from_le_bytes([address, address_inv])
+        };
+        self.last_code = Some((address, command, at));
+        Some(IrEvent::Pressed {
+            address,
+            command,
+            at,
+        })
+    }
+
+    fn repeat(&mut self, at: Instant) -> Option<IrEvent> {
+        let (address, command, last) = self.last_code?;
+        if at.saturating_duration_since(last) > REPEAT_WINDOW {
+            self.last_code = None;
+            return None;
+        }
+        self.last_code = Some((address, command, at));
+        Some(IrEvent::Repeat {
+            address,
+            command,
+            at,
+        })
+    }
+}
+
+impl Default for NecDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct ButtonMapError(String);
+
+impl fmt::Display for ButtonMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad button map line: '{}'", self.0)
+    }
+}
+
+impl Error for ButtonMapError {}
+
+// Names for the codes of a remote.
+pub struct ButtonMap {
+    names: HashMap<(u16, u8), String>,
+}
+
+impl ButtonMap {
+    // One `<name> <address> <command>` per line, numbers in decimal or 0x hex, e.g.
+    // `power 0x00 0x45`. Blank lines and lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Self, ButtonMapError> {
+        let parse_number = |s: &str| match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        };
+        let mut names = HashMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let code = match fields[..] {
+                [name, address, command] => parse_number(address)
+                    .zip(parse_number(command).and_then(|c| u8::try_from(c).ok()))
+                    .map(|code| (name, code)),
+                _ => None,
+            };
+            match code {
+                Some((name, code)) => names.insert(code, name.to_string()),
+                None => return Err(ButtonMapError(line.to_string())),
+            };
+        }
+        Ok(ButtonMap { names })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::parse(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn name(&self, address: u16, command: u8) -> Option<&str> {
+        self.names.get(&(address, command)).map(String::as_str)
+    }
+}
+
+// The kit's receiver module, output to a GPIO. The GPIO interrupt carries no
+// timestamp, so each edge is timed as it is read: if this thread falls behind, the
+// edges queued meanwhile come out bunched together and that frame is lost. Running
+// under `with_realtime` keeps the delay down to tens of microseconds.
+pub struct IrReceiver {
+    pin: InputPin,
+    decoder: NecDecoder,
+    realtime: Option<Realtime>,
+}
+
+impl IrReceiver {
+    pub fn new(pin: u8) -> Result<Self, rppal::gpio::Error> {
+        let mut pin = Gpio::new()?.get(pin)?.into_input_pullup();
+        pin.set_interrupt(Trigger::Both)?;
+        Ok(IrReceiver {
+            pin,
+            decoder: NecDecoder::new(),
+            realtime: None,
+        })
+    }
+
+    // Waits for edges under SCHED_FIFO, see `timing::Realtime`.
+    pub fn with_realtime(mut self, realtime: Realtime) -> Self {
+        self.realtime = Some(realtime);
+        self
+    }
+
+    // Waits for the next frame or repeat. Returns `None` if `timeout` passes first.
+    pub fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<IrEvent>, rppal::gpio::Error> {
+        // Best effort, as for the DHT: without the privileges edges are timed at
+        // normal priority.
+        let _realtime = self.realtime.and_then(|r| r.enter().ok());
+        let started = Instant::now();
+        loop {
+            let wait = timeout.map(|t| t.saturating_sub(started.elapsed()));
+            let level = self.pin.poll_interrupt(false, wait)?;
+            let at = Instant::now();
+            match level {
+                Some(level) => {
+                    if let Some(event) = self.decoder.edge(level, at) {
+                        return Ok(Some(event));
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // Calls `callback` with every event until `running` is cleared.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        mut callback: F,
+    ) -> Result<(), rppal::gpio::Error>
+    where
+        F: FnMut(IrEvent),
+    {
+        while running.load(Ordering::SeqCst) {
+            if let Some(event) = self.poll(Some(Duration::from_millis(100)))? {
+                callback(event);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(mut self) -> Receiver<IrEvent> {
+        button::spawn_events("ir receiver", move |running, send| self.run(running, send))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays edges into a decoder on a made-up clock.
+    struct Remote {
+        decoder: NecDecoder,
+        now: Instant,
+    }
+
+    impl Remote {
+        fn new() -> Self {
+            Remote {
+                decoder: NecDecoder::new(),
+                now: Instant::now(),
+            }
+        }
+
+        // Waits `after`, then the line goes to `level`.
+        fn edge(&mut self, after: Duration, level: Level) -> Option<IrEvent> {
+            self.now += after;
+            self.decoder.edge(level, self.now)
+        }
+
+        // Leader, 32 bits least significant first and the stop mark.
+        fn frame(&mut self, bits: u32) -> Vec<IrEvent> {
+            let mut events = vec![];
+            events.extend(self.edge(Duration::from_millis(50), Level::Low));
+            events.extend(self.edge(LEADER_MARK, Level::High));
+            events.extend(self.edge(LEADER_SPACE, Level::Low));
+            for i in 0..32 {
+                events.extend(self.edge(BIT_MARK, Level::High));
+                let space = if bits >> i & 1 == 1 {
+                    ONE_SPACE
+                } else {
+                    ZERO_SPACE
+                };
+                events.extend(self.edge(space, Level::Low));
+            }
+            events.extend(self.edge(BIT_MARK, Level::High));
+            events
+        }
+
+        fn repeat(&mut self, gap: Duration) -> Vec<IrEvent> {
+            let mut events = vec![];
+            events.extend(self.edge(gap, Level::Low));
+            events.extend(self.edge(LEADER_MARK, Level::High));
+            events.extend(self.edge(REPEAT_SPACE, Level::Low));
+            events.extend(self.edge(BIT_MARK, Level::High));
+            events
+        }
+    }
+
+    fn code(event: &IrEvent) -> (bool, u16, u8) {
+        match *event {
+            IrEvent::Pressed {
+                address, command, ..
+            } => (false, address, command),
+            IrEvent::Repeat {
+                address, command, ..
+            } => (true, address, command),
+        }
+    }
+
+    #[test]
+    fn decodes_a_frame() {
+        let mut remote = Remote::new();
+        // Address 0x00, command 0x45: the power key of the kit's remote.
+        let events = remote.frame(u32::from_le_bytes([0x00, 0xff, 0x45, 0xba]));
+        assert_eq!(
+            events.iter().map(code).collect::<Vec<_>>(),
+            [(false, 0x00, 0x45)]
+        );
+    }
+
+    #[test]
+    fn repeats_within_the_window() {
+        let mut remote = Remote::new();
+        remote.frame(u32::from_le_bytes([0x00, 0xff, 0x18, 0xe7]));
+        let events = remote.repeat(Duration::from_millis(40));
+        assert_eq!(
+            events.iter().map(code).collect::<Vec<_>>(),
+            [(true, 0x00, 0x18)]
+        );
+        // Each repeat restarts the window.
+        let events = remote.repeat(Duration::from_millis(96));
+        assert_eq!(events.len(), 1);
+        // Too late to belong to the held key, and nothing after it either.
+        assert!(remote.repeat(Duration::from_millis(250)).is_empty());
+        assert!(remote.repeat(Duration::from_millis(96)).is_empty());
+    }
+
+    #[test]
+    fn repeat_without_frame_is_ignored() {
+        let mut remote = Remote::new();
+        assert!(remote.repeat(Duration::from_millis(10)).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_inverted_command() {
+        let mut remote = Remote::new();
+        assert!(remote
+            .frame(u32::from_le_bytes([0x00, 0xff, 0x45, 0xbb]))
+            .is_empty());
+        // The next good frame still decodes.
+        assert_eq!(
+            remote
+                .frame(u32::from_le_bytes([0x00, 0xff, 0x46, 0xb9]))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn extended_address() {
+        let mut remote = Remote::new();
+        let events = remote.frame(u32::from_le_bytes([0x34, 0x12, 0x07, 0xf8]));
+        assert_eq!(
+            events.iter().map(code).collect::<Vec<_>>(),
+            [(false, 0x1234, 0x07)]
+        );
+    }
+
+    #[test]
+    fn parses_button_map() {
+        let map = ButtonMap::parse("# kit remote\n\npower 0x00 0x45\nmenu 0X00 0X47\nok 4660 64\n")
+            .unwrap();
+        assert_eq!(map.name(0x00, 0x45), Some("power"));
+        assert_eq!(map.name(0x00, 0x47), Some("menu"));
+        assert_eq!(map.name(0x1234, 0x40), Some("ok"));
+        assert!(ButtonMap::parse("power 0x00").is_err());
+        assert!(ButtonMap::parse("power 0x00 0x145").is_err());
+    }
+}
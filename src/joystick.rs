@@ -0,0 +1,470 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level};
+
+use crate::analog::AnalogInput;
+use crate::button::{ButtonConfig, ButtonEvent, ButtonMachine};
+
+// Raw readings as fractions of full scale, so a calibration survives a change of ADC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisCalibration {
+    pub min: f64,
+    pub center: f64,
+    pub max: f64,
+}
+
+impl AxisCalibration {
+    // Both halves need some travel, or `normalize` has nothing to scale by.
+    pub fn is_valid(&self) -> bool {
+        self.min < self.center && self.center < self.max
+    }
+
+    // -1.0..=1.0, with the two halves scaled separately since few sticks are symmetric.
+    pub fn normalize(&self, raw: f64) -> f64 {
+        let value = if raw >= self.center {
+            (raw - self.center) / (self.max - self.center)
+        } else {
+            (raw - self.center) / (self.center - self.min)
+        };
+        if value.is_finite() {
+            value.clamp(-1.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        AxisCalibration {
+            min: 0.0,
+            center: 0.5,
+            max: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Calibration {
+    pub x: AxisCalibration,
+    pub y: AxisCalibration,
+}
+
+#[derive(Debug)]
+pub struct CalibrationParseError(String);
+
+impl fmt::Display for CalibrationParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad calibration: {}", self.0)
+    }
+}
+
+impl Error for CalibrationParseError {}
+
+impl Calibration {
+    // Two lines, `x <min> <center> <max>` and `y <min> <center> <max>`.
+    pub fn parse(text: &str) -> Result<Self, CalibrationParseError> {
+        let mut calibration = Calibration::default();
+        let mut seen = (false, false);
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let values = fields[1..]
+                .iter()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| CalibrationParseError(format!("'{}'", line)))?;
+            if values.len() != 3 {
+                return Err(CalibrationParseError(format!("'{}'", line)));
+            }
+            let axis = AxisCalibration {
+                min: values[0],
+                center: values[1],
+                max: values[2],
+            };
+            if !axis.is_valid() {
+                return Err(CalibrationParseError(format!("'{}'", line)));
+            }
+            match fields[0] {
+                "x" => {
+                    calibration.x = axis;
+                    seen.0 = true;
+                }
+                "y" => {
+                    calibration.y = axis;
+                    seen.1 = true;
+                }
+                _ => return Err(CalibrationParseError(format!("'{}'", line))),
+            }
+        }
+        if seen != (true, true) {
+            return Err(CalibrationParseError("need both x and y".to_string()));
+        }
+        Ok(calibration)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::parse(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        fs::write(
+            path,
+            format!(
+                "x {} {} {}\ny {} {} {}\n",
+                self.x.min, self.x.center, self.x.max, self.y.min, self.y.center, self.y.max
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Center,
+    Up,
+    UpRight,
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+}
+
+impl Direction {
+    // Up is +y. The stick has to leave the center by `threshold` to count.
+    pub fn from_axes(x: f64, y: f64, threshold: f64) -> Direction {
+        if x.hypot(y) < threshold {
+            return Direction::Center;
+        }
+        let sector = (y.atan2(x).to_degrees() / 45.0).round() as i32;
+        match sector.rem_euclid(8) {
+            0 => Direction::Right,
+            1 => Direction::UpRight,
+            2 => Direction::Up,
+            3 => Direction::UpLeft,
+            4 => Direction::Left,
+            5 => Direction::DownLeft,
+            6 => Direction::Down,
+            _ => Direction::DownRight,
+        }
+    }
+}
+
+// Zero within `deadzone` of the center, then rescaled so the output still starts at
+// zero at the deadzone's edge and reaches 1.0 at full deflection, raised to `curve`.
+// The direction is kept.
+fn shape(x: f64, y: f64, deadzone: f64, curve: f64) -> (f64, f64) {
+    let magnitude = x.hypot(y);
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+    let scaled = ((magnitude.min(1.0) - deadzone) / (1.0 - deadzone)).powf(curve);
+    (x / magnitude * scaled, y / magnitude * scaled)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoystickEvent {
+    Direction(Direction),
+    Button(ButtonEvent),
+}
+
+pub struct Joystick<A: AnalogInput> {
+    adc: A,
+    x_channel: u8,
+    y_channel: u8,
+    button: InputPin,
+    active_level: Level,
+    button_machine: ButtonMachine,
+    calibration: Calibration,
+    invert_x: bool,
+    invert_y: bool,
+    deadzone: f64,
+    curve: f64,
+    direction: Direction,
+    position: (f64, f64),
+}
+
+impl<A: AnalogInput> Joystick<A> {
+    // The kit's module switches its button to ground.
+    pub fn new(
+        adc: A,
+        x_channel: u8,
+        y_channel: u8,
+        button_pin: u8,
+    ) -> Result<Self, Box<dyn Error>> {
+        let button = Gpio::new()?.get(button_pin)?.into_input_pullup();
+        let config = ButtonConfig::default();
+        let pressed = button.read() == config.active_level;
+        Ok(Joystick {
+            adc,
+            x_channel,
+            y_channel,
+            button,
+            active_level: config.active_level,
+            button_machine: ButtonMachine::new(config, pressed, Instant::now()),
+            calibration: Calibration::default(),
+            invert_x: false,
+            invert_y: false,
+            deadzone: 0.1,
+            curve: 1.0,
+            direction: Direction::Center,
+            position: (0.0, 0.0),
+        })
+    }
+
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    pub fn with_inversion(mut self, invert_x: bool, invert_y: bool) -> Self {
+        self.invert_x = invert_x;
+        self.invert_y = invert_y;
+        self
+    }
+
+    // Radius around the center, as a fraction of full deflection, that reads as zero.
+    pub fn with_deadzone(mut self, deadzone: f64) -> Self {
+        self.deadzone = deadzone.clamp(0.0, 0.99);
+        self
+    }
+
+    // Exponent applied to the deflection. Above 1.0 gives finer control near the center.
+    pub fn with_curve(mut self, curve: f64) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    pub fn read_raw(&mut self) -> Result<(f64, f64), Box<dyn Error>> {
+        Ok((
+            self.adc.read_normalized(self.x_channel)?,
+            self.adc.read_normalized(self.y_channel)?,
+        ))
+    }
+
+    // Both axes in -1.0..=1.0 after calibration, deadzone and response curve.
+    pub fn read(&mut self) -> Result<(f64, f64), Box<dyn Error>> {
+        let (raw_x, raw_y) = self.read_raw()?;
+        let mut x = self.calibration.x.normalize(raw_x);
+        let mut y = self.calibration.y.normalize(raw_y);
+        if self.invert_x {
+            x = -x;
+        }
+        if self.invert_y {
+            y = -y;
+        }
+        Ok(shape(x, y, self.deadzone, self.curve))
+    }
+
+    // Where the stick was at the last `poll`.
+    pub fn position(&self) -> (f64, f64) {
+        self.position
+    }
+
+    // Samples the stick and the button once and returns what changed.
+    pub fn poll(&mut self) -> Result<Vec<JoystickEvent>, Box<dyn Error>> {
+        let now = Instant::now();
+        self.button_machine
+            .edge(self.button.read() == self.active_level, now);
+        let mut events: Vec<JoystickEvent> = self
+            .button_machine
+            .tick(now)
+            .into_iter()
+            .map(JoystickEvent::Button)
+            .collect();
+
+        let (x, y) = self.read()?;
+        self.position = (x, y);
+        // Leaving a direction takes less deflection than entering one, so the event
+        // does not flicker at the edge.
+        let threshold = if self.direction == Direction::Center {
+            0.5
+        } else {
+            0.4
+        };
+        let direction = Direction::from_axes(x, y, threshold);
+        if direction != self.direction {
+            self.direction = direction;
+            events.push(JoystickEvent::Direction(direction));
+        }
+        Ok(events)
+    }
+
+    // Asks on the terminal for the stick to be left centered and then moved around,
+    // and returns the calibration found. The joystick starts using it as well.
+    pub fn calibrate_interactive(&mut self) -> Result<Calibration, Box<dyn Error>> {
+        let wait_enter = |prompt: &str| -> io::Result<()> {
+            print!("{}", prompt);
+            io::stdout().flush()?;
+            io::stdin().lock().read_line(&mut String::new())?;
+            Ok(())
+        };
+
+        wait_enter("Leave the stick centered and press Enter.")?;
+        let samples = 50;
+        let (mut sum_x, mut sum_y) = (0.0, 0.0);
+        for _ in 0..samples {
+            let (x, y) = self.read_raw()?;
+            sum_x += x;
+            sum_y += y;
+            thread::sleep(Duration::from_millis(10));
+        }
+        let center = (sum_x / samples as f64, sum_y / samples as f64);
+
+        wait_enter("Press Enter, then move the stick around its full range for 5 seconds.")?;
+        let (mut min, mut max) = (center, center);
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            let (x, y) = self.read_raw()?;
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let calibration = Calibration {
+            x: AxisCalibration {
+                min: min.0,
+                center: center.0,
+                max: max.0,
+            },
+            y: AxisCalibration {
+                min: min.1,
+                center: center.1,
+                max: max.1,
+            },
+        };
+        // `parse` would refuse to load it back.
+        if !(calibration.x.is_valid() && calibration.y.is_valid()) {
+            return Err(Box::new(CalibrationParseError(
+                "the stick did not move both ways on both axes".to_string(),
+            )));
+        }
+        self.calibration = calibration;
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn normalizes_each_half_separately() {
+        let axis = AxisCalibration {
+            min: 0.1,
+            center: 0.4,
+            max: 0.9,
+        };
+        assert_close(axis.normalize(0.4), 0.0);
+        assert_close(axis.normalize(0.9), 1.0);
+        assert_close(axis.normalize(0.1), -1.0);
+        assert_close(axis.normalize(0.65), 0.5);
+        assert_close(axis.normalize(0.25), -0.5);
+        // Beyond the calibrated travel.
+        assert_close(axis.normalize(1.0), 1.0);
+        assert_close(axis.normalize(0.0), -1.0);
+
+        // A degenerate half reads as centered rather than NaN.
+        let flat = AxisCalibration {
+            min: 0.5,
+            center: 0.5,
+            max: 1.0,
+        };
+        assert!(!flat.is_valid());
+        assert_close(flat.normalize(0.2), 0.0);
+    }
+
+    #[test]
+    fn parses_and_round_trips() {
+        let calibration = Calibration::parse("x 0.02 0.51 0.98\n\ny 0.05 0.49 0.97\n").unwrap();
+        assert_eq!(
+            calibration.x,
+            AxisCalibration {
+                min: 0.02,
+                center: 0.51,
+                max: 0.98
+            }
+        );
+        assert_close(calibration.y.center, 0.49);
+
+        let path = std::env::temp_dir().join(format!("joystick-{}.cal", std::process::id()));
+        calibration.save(&path).unwrap();
+        let loaded = Calibration::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap(), calibration);
+    }
+
+    #[test]
+    fn rejects_bad_calibrations() {
+        for text in [
+            "x 0 0.5 1",
+            "x 0 0.5 1\ny 0 0.5",
+            "x 0 0.5 1\ny 0 half 1",
+            "x 0 0.5 1\nz 0 0.5 1",
+            "x 0 0.5 1\ny 0.5 0.5 1",
+            "x 0 0.5 1\ny 0 0.7 0.6",
+        ] {
+            assert!(Calibration::parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!(Direction::from_axes(0.0, 0.0, 0.5), Direction::Center);
+        assert_eq!(Direction::from_axes(0.3, 0.3, 0.5), Direction::Center);
+        assert_eq!(Direction::from_axes(1.0, 0.0, 0.5), Direction::Right);
+        assert_eq!(Direction::from_axes(0.0, 1.0, 0.5), Direction::Up);
+        assert_eq!(Direction::from_axes(-1.0, 0.0, 0.5), Direction::Left);
+        assert_eq!(Direction::from_axes(0.0, -1.0, 0.5), Direction::Down);
+        assert_eq!(Direction::from_axes(0.7, 0.7, 0.5), Direction::UpRight);
+        assert_eq!(Direction::from_axes(-0.7, 0.7, 0.5), Direction::UpLeft);
+        assert_eq!(Direction::from_axes(-0.7, -0.7, 0.5), Direction::DownLeft);
+        assert_eq!(Direction::from_axes(0.7, -0.7, 0.5), Direction::DownRight);
+        // Sectors are 45 degrees wide: 20 degrees off is still straight right.
+        let (sin, cos) = 20f64.to_radians().sin_cos();
+        assert_eq!(Direction::from_axes(cos, sin, 0.5), Direction::Right);
+        let (sin, cos) = 25f64.to_radians().sin_cos();
+        assert_eq!(Direction::from_axes(cos, sin, 0.5), Direction::UpRight);
+    }
+
+    #[test]
+    fn deadzone_and_curve() {
+        assert_eq!(shape(0.05, -0.05, 0.1, 1.0), (0.0, 0.0));
+        // Just past the deadzone starts from zero.
+        let (x, _) = shape(0.1 + 1e-6, 0.0, 0.1, 1.0);
+        assert!(x > 0.0 && x < 1e-5);
+        let (x, y) = shape(0.55, 0.0, 0.1, 1.0);
+        assert_close(x, 0.5);
+        assert_close(y, 0.0);
+        assert_eq!(shape(0.0, -1.0, 0.1, 1.0), (0.0, -1.0));
+        // Diagonals past full deflection are capped, keeping their direction.
+        let (x, y) = shape(1.0, 1.0, 0.1, 1.0);
+        assert_close(x.hypot(y), 1.0);
+        assert_close(x, y);
+
+        // A curve of 2 halves the response half way out.
+        let (x, _) = shape(0.55, 0.0, 0.1, 2.0);
+        assert_close(x, 0.25);
+        let (_, y) = shape(0.0, 1.0, 0.1, 2.0);
+        assert_close(y, 1.0);
+    }
+}
@@ -0,0 +1,350 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, OutputPin};
+
+use crate::button;
+
+pub const KEYS_4X4: [&[char]; 4] = [
+    &['1', '2', '3', 'A'],
+    &['4', '5', '6', 'B'],
+    &['7', '8', '9', 'C'],
+    &['*', '0', '#', 'D'],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    KeyDown { key: char, at: Instant },
+    KeyUp { key: char, at: Instant },
+    // Three or more keys are held so that a phantom key cannot be told apart from a
+    // real one. Scans are ignored until the ambiguity clears.
+    Ghosting { at: Instant },
+}
+
+#[derive(Debug)]
+pub enum KeypadError {
+    GpioError(rppal::gpio::Error),
+    Layout(String),
+}
+
+impl fmt::Display for KeypadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeypadError::GpioError(e) => write!(f, "gpio error: {}", e),
+            KeypadError::Layout(msg) => write!(f, "bad key layout: {}", msg),
+        }
+    }
+}
+
+impl Error for KeypadError {}
+
+impl From<rppal::gpio::Error> for KeypadError {
+    fn from(e: rppal::gpio::Error) -> KeypadError {
+        KeypadError::GpioError(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct KeyState {
+    raw: bool,
+    raw_since: Instant,
+    pressed: bool,
+}
+
+// Debounces and decodes raw scans. Scans are row-major, one entry per key.
+pub struct KeypadMachine {
+    keys: Vec<char>,
+    rows: usize,
+    cols: usize,
+    debounce: Duration,
+    states: Vec<KeyState>,
+    ghosting: bool,
+}
+
+impl KeypadMachine {
+    pub fn new(keymap: &[&[char]], debounce: Duration, now: Instant) -> Result<Self, KeypadError> {
+        let rows = keymap.len();
+        let cols = keymap.first().map_or(0, |r| r.len());
+        if rows == 0 || cols == 0 {
+            return Err(KeypadError::Layout("no keys".to_string()));
+        }
+        if keymap.iter().any(|r| r.len() != cols) {
+            return Err(KeypadError::Layout("rows differ in length".to_string()));
+        }
+        let state = KeyState {
+            raw: false,
+            raw_since: now,
+            pressed: false,
+        };
+        Ok(KeypadMachine {
+            keys: keymap.iter().flat_map(|r| r.iter().copied()).collect(),
+            rows,
+            cols,
+            debounce,
+            states: vec![state; rows * cols],
+            ghosting: false,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn pressed(&self) -> Vec<char> {
+        self.keys
+            .iter()
+            .zip(&self.states)
+            .filter(|(_, s)| s.pressed)
+            .map(|(k, _)| *k)
+            .collect()
+    }
+
+    // A pressed key that shares its row with one pressed key and its column with
+    // another closes a rectangle, whose fourth corner then reads as pressed too.
+    pub fn is_ghosting(&self, scan: &[bool]) -> bool {
+        let pressed = |r: usize, c: usize| scan[r * self.cols + c];
+        (0..self.rows).any(|r| {
+            (0..self.cols).any(|c| {
+                pressed(r, c)
+                    && (0..self.cols).any(|c2| c2 != c && pressed(r, c2))
+                    && (0..self.rows).any(|r2| r2 != r && pressed(r2, c))
+            })
+        })
+    }
+
+    pub fn update(&mut self, scan: &[bool], now: Instant) -> Vec<KeyEvent> {
+        assert_eq!(scan.len(), self.states.len(), "one scan entry per key");
+        let mut events = vec![];
+        if self.is_ghosting(scan) {
+            if !self.ghosting {
+                self.ghosting = true;
+                events.push(KeyEvent::Ghosting { at: now });
+            }
+            return events;
+        }
+        self.ghosting = false;
+        for ((key, state), raw) in self.keys.iter().zip(&mut self.states).zip(scan) {
+            if *raw != state.raw {
+                state.raw = *raw;
+                state.raw_since = now;
+            }
+            if state.raw != state.pressed && now >= state.raw_since + self.debounce {
+                state.pressed = state.raw;
+                let at = state.raw_since;
+                events.push(if state.pressed {
+                    KeyEvent::KeyDown { key: *key, at }
+                } else {
+                    KeyEvent::KeyUp { key: *key, at }
+                });
+            }
+        }
+        events
+    }
+}
+
+pub struct MatrixKeypad {
+    row_pins: Vec<OutputPin>,
+    col_pins: Vec<InputPin>,
+    machine: KeypadMachine,
+    scan_interval: Duration,
+}
+
+impl MatrixKeypad {
+    // Rows are driven high one at a time and the columns read back, so the column pins
+    // are pulled down.
+    pub fn new(row_pins: &[u8], col_pins: &[u8], keymap: &[&[char]]) -> Result<Self, KeypadError> {
+        let machine = KeypadMachine::new(keymap, Duration::from_millis(20), Instant::now())?;
+        if machine.rows() != row_pins.len() || machine.cols() != col_pins.len() {
+            return Err(KeypadError::Layout(format!(
+                "{}x{} keys on {} rows and {} columns",
+                machine.rows(),
+                machine.cols(),
+                row_pins.len(),
+                col_pins.len()
+            )));
+        }
+        let gpio = Gpio::new()?;
+        let row_pins = row_pins
+            .iter()
+            .map(|pin| Ok(gpio.get(*pin)?.into_output_low()))
+            .collect::<Result<Vec<_>, KeypadError>>()?;
+        let col_pins = col_pins
+            .iter()
+            .map(|pin| Ok(gpio.get(*pin)?.into_input_pulldown()))
+            .collect::<Result<Vec<_>, KeypadError>>()?;
+        Ok(MatrixKeypad {
+            row_pins,
+            col_pins,
+            machine,
+            scan_interval: Duration::from_millis(10),
+        })
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.machine.debounce = debounce;
+        self
+    }
+
+    pub fn with_scan_interval(mut self, interval: Duration) -> Self {
+        self.scan_interval = interval;
+        self
+    }
+
+    pub fn pressed(&self) -> Vec<char> {
+        self.machine.pressed()
+    }
+
+    pub fn scan(&mut self) -> Vec<bool> {
+        let mut scan = Vec::with_capacity(self.row_pins.len() * self.col_pins.len());
+        for row in &mut self.row_pins {
+            row.set_high();
+            thread::sleep(Duration::from_micros(10));
+            scan.extend(self.col_pins.iter().map(|col| col.is_high()));
+            row.set_low();
+        }
+        scan
+    }
+
+    pub fn poll(&mut self) -> Vec<KeyEvent> {
+        let scan = self.scan();
+        self.machine.update(&scan, Instant::now())
+    }
+
+    // Calls `callback` with every event until `running` is cleared.
+    pub fn run<F>(&mut self, running: &AtomicBool, mut callback: F)
+    where
+        F: FnMut(KeyEvent),
+    {
+        while running.load(Ordering::SeqCst) {
+            for event in self.poll() {
+                callback(event);
+            }
+            thread::sleep(self.scan_interval);
+        }
+    }
+
+    pub fn spawn(mut self) -> Receiver<KeyEvent> {
+        button::spawn_events("keypad", move |running, send| {
+            self.run(running, send);
+            Ok::<(), Infallible>(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS_2X3: [&[char]; 2] = [&['1', '2', '3'], &['4', '5', '6']];
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // A scan of the 2x3 pad with `keys` held.
+    fn scan(keys: &str) -> Vec<bool> {
+        "123456".chars().map(|key| keys.contains(key)).collect()
+    }
+
+    fn machine(t0: Instant) -> KeypadMachine {
+        KeypadMachine::new(&KEYS_2X3, ms(20), t0).unwrap()
+    }
+
+    #[test]
+    fn rejects_bad_layouts() {
+        let t0 = Instant::now();
+        assert!(KeypadMachine::new(&[], ms(20), t0).is_err());
+        assert!(KeypadMachine::new(&[&['1', '2'], &['3']], ms(20), t0).is_err());
+    }
+
+    #[test]
+    fn keys_are_debounced_separately() {
+        let t0 = Instant::now();
+        let mut machine = machine(t0);
+        assert!(machine.update(&scan("1"), t0).is_empty());
+        assert!(machine.update(&scan("15"), t0 + ms(10)).is_empty());
+        // `1` has been down 20 ms, `5` only 10.
+        assert_eq!(
+            machine.update(&scan("15"), t0 + ms(20)),
+            [KeyEvent::KeyDown { key: '1', at: t0 }]
+        );
+        assert_eq!(
+            machine.update(&scan("15"), t0 + ms(30)),
+            [KeyEvent::KeyDown {
+                key: '5',
+                at: t0 + ms(10)
+            }]
+        );
+        assert_eq!(machine.pressed(), ['1', '5']);
+
+        // A bounce on release does not count until it settles.
+        assert!(machine.update(&scan("5"), t0 + ms(40)).is_empty());
+        assert!(machine.update(&scan("15"), t0 + ms(45)).is_empty());
+        assert!(machine.update(&scan("5"), t0 + ms(50)).is_empty());
+        assert_eq!(
+            machine.update(&scan("5"), t0 + ms(70)),
+            [KeyEvent::KeyUp {
+                key: '1',
+                at: t0 + ms(50)
+            }]
+        );
+        assert_eq!(machine.pressed(), ['5']);
+    }
+
+    #[test]
+    fn short_blip_is_ignored() {
+        let t0 = Instant::now();
+        let mut machine = machine(t0);
+        assert!(machine.update(&scan("3"), t0).is_empty());
+        assert!(machine.update(&scan(""), t0 + ms(5)).is_empty());
+        assert!(machine.update(&scan(""), t0 + ms(50)).is_empty());
+        assert!(machine.pressed().is_empty());
+    }
+
+    #[test]
+    fn ghost_rectangles() {
+        let machine = machine(Instant::now());
+        // Same row or same column is fine.
+        assert!(!machine.is_ghosting(&scan("123")));
+        assert!(!machine.is_ghosting(&scan("14")));
+        // Diagonal pairs do not close a rectangle.
+        assert!(!machine.is_ghosting(&scan("15")));
+        assert!(!machine.is_ghosting(&scan("16")));
+        // Three corners of a rectangle, whichever they are.
+        assert!(machine.is_ghosting(&scan("124")));
+        assert!(machine.is_ghosting(&scan("346")));
+        assert!(machine.is_ghosting(&scan("1245")));
+    }
+
+    #[test]
+    fn ghosting_is_reported_once_and_scans_are_held() {
+        let t0 = Instant::now();
+        let mut machine = machine(t0);
+        machine.update(&scan("12"), t0);
+        machine.update(&scan("12"), t0 + ms(20));
+        assert_eq!(
+            machine.update(&scan("124"), t0 + ms(30)),
+            [KeyEvent::Ghosting { at: t0 + ms(30) }]
+        );
+        assert!(machine.update(&scan("124"), t0 + ms(60)).is_empty());
+        assert_eq!(machine.pressed(), ['1', '2']);
+        // Once it clears, scans count again.
+        assert!(machine.update(&scan("1"), t0 + ms(70)).is_empty());
+        assert_eq!(
+            machine.update(&scan("1"), t0 + ms(90)),
+            [KeyEvent::KeyUp {
+                key: '2',
+                at: t0 + ms(70)
+            }]
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::blocking::i2c::Write;
+
+use crate::timing;
+
+// PCF8574. The PCF8574A backpacks answer at 0x3f instead.
+pub const DEFAULT_ADDRESS: u8 = 0x27;
+
+pub const COLUMNS: usize = 16;
+pub const ROWS: usize = 2;
+
+// How the backpack wires the expander to the HD44780: P0 RS, P1 RW, P2 E,
+// P3 backlight, P4-P7 D4-D7.
+const RS: u8 = 0x01;
+const ENABLE: u8 = 0x04;
+const BACKLIGHT: u8 = 0x08;
+
+const CLEAR_DISPLAY: u8 = 0x01;
+const ENTRY_MODE_INCREMENT: u8 = 0x06;
+const DISPLAY_CONTROL: u8 = 0x08;
+const DISPLAY_ON: u8 = 0x04;
+const CURSOR_ON: u8 = 0x02;
+const BLINK_ON: u8 = 0x01;
+// 4-bit bus, two lines, 5x8 font.
+const FUNCTION_SET_4BIT_2LINE: u8 = 0x28;
+const SET_CGRAM_ADDRESS: u8 = 0x40;
+const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+// Glyphs are 8 rows of 5 pixels, top first, in the low bits of each byte. 1 to 5
+// columns filled from the left, for `bar_graph`.
+pub const BAR_GLYPHS: [[u8; 8]; 5] = [[0x10; 8], [0x18; 8], [0x1c; 8], [0x1e; 8], [0x1f; 8]];
+
+// A horizontal bar `width` cells wide, filled to `fraction`, with the glyphs of
+// `BAR_GLYPHS` loaded from `first_slot` on.
+pub fn bar_graph(fraction: f64, width: usize, first_slot: u8) -> String {
+    let columns = (fraction.clamp(0.0, 1.0) * (width * 5) as f64).round() as usize;
+    (0..width)
+        .map(|cell| match columns.saturating_sub(cell * 5).min(5) {
+            0 => ' ',
+            n => char::from(first_slot + n as u8 - 1),
+        })
+        .collect()
+}
+
+// The display's character code for `c`. `'\0'`-`'\x07'` are the CGRAM slots.
+fn char_code(c: char) -> u8 {
+    match c {
+        '\0'..='\x07' | ' '..='}' => c as u8,
+        // The A00 character ROM has its own degree sign.
+        '°' => 0xdf,
+        _ => b'?',
+    }
+}
+
+#[derive(Debug)]
+pub enum Lcd1602Error<E> {
+    I2cError(E),
+    NoSuchPosition { col: u8, row: u8 },
+    NoSuchSlot(u8),
+}
+
+impl<E: fmt::Debug> fmt::Display for Lcd1602Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lcd1602Error::I2cError(e) => write!(f, "i2c error: {:?}", e),
+            Lcd1602Error::NoSuchPosition { col, row } => {
+                write!(f, "no position ({}, {}) on the display", col, row)
+            }
+            Lcd1602Error::NoSuchSlot(slot) => write!(f, "no glyph slot {}", slot),
+        }
+    }
+}
+
+impl<E: fmt::Debug> Error for Lcd1602Error<E> {}
+
+// Works with any blocking I2C bus, e.g. `rppal::i2c::I2c`. Writes only; the RW line
+// stays low, so the busy flag is never read and commands are given time instead.
+pub struct Lcd1602<I2C> {
+    i2c: I2C,
+    address: u8,
+    backlight: bool,
+    display_control: u8,
+    // What the display shows, so `write_line` can skip unchanged characters.
+    shadow: [[u8; COLUMNS]; ROWS],
+    // Where the next character goes, if known.
+    cursor: Option<(u8, u8)>,
+}
+
+impl<I2C, E> Lcd1602<I2C>
+where
+    I2C: Write<Error = E>,
+{
+    pub fn new(i2c: I2C, address: u8) -> Result<Self, Lcd1602Error<E>> {
+        let mut lcd = Lcd1602 {
+            i2c,
+            address,
+            backlight: true,
+            display_control: DISPLAY_ON,
+            shadow: [[b' '; COLUMNS]; ROWS],
+            cursor: None,
+        };
+        lcd.init()?;
+        Ok(lcd)
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    // The controller powers up in 8-bit mode, or in an unknown nibble phase after a
+    // restart of the program, so it is forced to 8-bit three times before switching.
+    fn init(&mut self) -> Result<(), Lcd1602Error<E>> {
+        thread::sleep(Duration::from_millis(50));
+        self.write_nibble(0x30, 0)?;
+        thread::sleep(Duration::from_micros(4500));
+        self.write_nibble(0x30, 0)?;
+        thread::sleep(Duration::from_micros(150));
+        self.write_nibble(0x30, 0)?;
+        self.write_nibble(0x20, 0)?;
+
+        self.command(FUNCTION_SET_4BIT_2LINE)?;
+        self.command(DISPLAY_CONTROL | self.display_control)?;
+        self.command(ENTRY_MODE_INCREMENT)?;
+        self.clear()
+    }
+
+    pub fn clear(&mut self) -> Result<(), Lcd1602Error<E>> {
+        self.command(CLEAR_DISPLAY)?;
+        thread::sleep(Duration::from_millis(2));
+        self.shadow = [[b' '; COLUMNS]; ROWS];
+        self.cursor = Some((0, 0));
+        Ok(())
+    }
+
+    pub fn set_backlight(&mut self, on: bool) -> Result<(), Lcd1602Error<E>> {
+        self.backlight = on;
+        self.write_expander(0)
+    }
+
+    pub fn show_cursor(&mut self, on: bool, blink: bool) -> Result<(), Lcd1602Error<E>> {
+        self.display_control = DISPLAY_ON;
+        if on {
+            self.display_control |= CURSOR_ON;
+        }
+        if blink {
+            self.display_control |= BLINK_ON;
+        }
+        self.command(DISPLAY_CONTROL | self.display_control)
+    }
+
+    pub fn set_cursor(&mut self, col: u8, row: u8) -> Result<(), Lcd1602Error<E>> {
+        if col as usize >= COLUMNS || row as usize >= ROWS {
+            return Err(Lcd1602Error::NoSuchPosition { col, row });
+        }
+        // The second line starts at 0x40 in display RAM.
+        self.command(SET_DDRAM_ADDRESS | (row * 0x40 + col))?;
+        self.cursor = Some((col, row));
+        Ok(())
+    }
+
+    // Loads a glyph into one of the 8 CGRAM slots; print it as `'\0'`-`'\x07'`.
+    pub fn define_glyph(&mut self, slot: u8, glyph: &[u8; 8]) -> Result<(), Lcd1602Error<E>> {
+        if slot >= 8 {
+            return Err(Lcd1602Error::NoSuchSlot(slot));
+        }
+        self.command(SET_CGRAM_ADDRESS | slot << 3)?;
+        for row in glyph {
+            self.data(row & 0x1f)?;
+        }
+        // Back to display RAM. Characters already on screen change with the glyph.
+        self.cursor = None;
+        Ok(())
+    }
+
+    // Writes at the cursor. Characters past the end of the line are dropped.
+    pub fn write_str(&mut self, s: &str) -> Result<(), Lcd1602Error<E>> {
+        let (mut col, row) = match self.cursor {
+            Some(cursor) => cursor,
+            None => {
+                self.set_cursor(0, 0)?;
+                (0, 0)
+            }
+        };
+        for c in s.chars() {
+            if col as usize >= COLUMNS {
+                break;
+            }
+            let code = char_code(c);
+            self.data(code)?;
+            self.shadow[row as usize][col as usize] = code;
+            col += 1;
+        }
+        self.cursor = Some((col, row));
+        Ok(())
+    }
+
+    // Shows `s` on `row`, padded with spaces, sending only what differs from the
+    // screen.
+    pub fn write_line(&mut self, row: u8, s: &str) -> Result<(), Lcd1602Error<E>> {
+        if row as usize >= ROWS {
+            return Err(Lcd1602Error::NoSuchPosition { col: 0, row });
+        }
+        let mut line = [b' '; COLUMNS];
+        for (cell, c) in line.iter_mut().zip(s.chars()) {
+            *cell = char_code(c);
+        }
+        for (col, &code) in line.iter().enumerate() {
+            if self.shadow[row as usize][col] == code {
+                continue;
+            }
+            let col = col as u8;
+            if self.cursor != Some((col, row)) {
+                self.set_cursor(col, row)?;
+            }
+            self.data(code)?;
+            self.shadow[row as usize][col as usize] = code;
+            self.cursor = Some((col + 1, row));
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, value: u8) -> Result<(), Lcd1602Error<E>> {
+        self.write_byte(value, 0)
+    }
+
+    fn data(&mut self, value: u8) -> Result<(), Lcd1602Error<E>> {
+        self.write_byte(value, RS)
+    }
+
+    fn write_byte(&mut self, value: u8, mode: u8) -> Result<(), Lcd1602Error<E>> {
+        self.write_nibble(value & 0xf0, mode)?;
+        self.write_nibble(value << 4, mode)?;
+        // Most instructions take 37 µs; the I2C transfers already take longer than
+        // that at 100 kHz, but faster buses need the wait.
+        timing::delay_us(50);
+        Ok(())
+    }
+
+    // The upper four bits of `nibble`, latched on the falling edge of E.
+    fn write_nibble(&mut self, nibble: u8, mode: u8) -> Result<(), Lcd1602Error<E>> {
+        let bits = (nibble & 0xf0) | mode;
+        self.write_expander(bits | ENABLE)?;
+        timing::delay_us(1);
+        self.write_expander(bits)
+    }
+
+    fn write_expander(&mut self, bits: u8) -> Result<(), Lcd1602Error<E>> {
+        let backlight = if self.backlight { BACKLIGHT } else { 0 };
+        self.i2c
+            .write(self.address, &[bits | backlight])
+            .map_err(Lcd1602Error::I2cError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the bytes sent to the expander.
+    #[derive(Default)]
+    struct FakeBus {
+        writes: Vec<u8>,
+    }
+
+    impl Write for FakeBus {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.writes.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Sent {
+        Command(u8),
+        Data(u8),
+    }
+
+    // A display just set up, with what `new` sent forgotten.
+    fn lcd() -> Lcd1602<FakeBus> {
+        let mut lcd = Lcd1602::new(FakeBus::default(), DEFAULT_ADDRESS).unwrap();
+        lcd.i2c.writes.clear();
+        lcd
+    }
+
+    // Decodes the bytes latched by E, two nibbles at a time, and forgets them.
+    fn sent(lcd: &mut Lcd1602<FakeBus>) -> Vec<Sent> {
+        let latched: Vec<u8> = lcd
+            .i2c
+            .writes
+            .drain(..)
+            .filter(|bits| bits & ENABLE != 0)
+            .collect();
+        latched
+            .chunks(2)
+            .map(|pair| {
+                let byte = pair[0] & 0xf0 | pair[1] >> 4;
+                if pair[0] & RS != 0 {
+                    Sent::Data(byte)
+                } else {
+                    Sent::Command(byte)
+                }
+            })
+            .collect()
+    }
+
+    fn data(s: &str) -> Vec<Sent> {
+        s.bytes().map(Sent::Data).collect()
+    }
+
+    #[test]
+    fn character_codes() {
+        assert_eq!(char_code('A'), 0x41);
+        assert_eq!(char_code(' '), 0x20);
+        assert_eq!(char_code('}'), 0x7d);
+        assert_eq!(char_code('\x03'), 0x03);
+        assert_eq!(char_code('°'), 0xdf);
+        // Not in the ROM, or different there: 0x7e is an arrow.
+        assert_eq!(char_code('~'), b'?');
+        assert_eq!(char_code('é'), b'?');
+    }
+
+    #[test]
+    fn bar_graphs() {
+        assert_eq!(bar_graph(0.0, 3, 0), "   ");
+        assert_eq!(bar_graph(1.0, 2, 0), "\x04\x04");
+        // 5 of 10 columns.
+        assert_eq!(bar_graph(0.5, 2, 0), "\x04 ");
+        // 3 of 10 columns, from slot 2 on.
+        assert_eq!(bar_graph(0.3, 2, 2), "\x04 ");
+        assert_eq!(bar_graph(0.7, 2, 2), "\x06\x03");
+        assert_eq!(bar_graph(-1.0, 1, 0), " ");
+        assert_eq!(bar_graph(2.0, 1, 0), "\x04");
+    }
+
+    #[test]
+    fn write_line_sends_only_changes() {
+        let mut lcd = lcd();
+        lcd.write_line(0, "Hello").unwrap();
+        // The cursor is at the start after clearing, and spaces are already there.
+        assert_eq!(sent(&mut lcd), data("Hello"));
+
+        lcd.write_line(0, "Help").unwrap();
+        let mut expected = vec![Sent::Command(SET_DDRAM_ADDRESS | 3)];
+        expected.extend(data("p "));
+        assert_eq!(sent(&mut lcd), expected);
+
+        lcd.write_line(0, "Help").unwrap();
+        assert_eq!(sent(&mut lcd), []);
+
+        // Separate changes each move the cursor.
+        lcd.write_line(0, "Xelp!").unwrap();
+        assert_eq!(
+            sent(&mut lcd),
+            [
+                Sent::Command(SET_DDRAM_ADDRESS),
+                Sent::Data(b'X'),
+                Sent::Command(SET_DDRAM_ADDRESS | 4),
+                Sent::Data(b'!'),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_line_on_the_second_row() {
+        let mut lcd = lcd();
+        lcd.write_line(1, "  42°").unwrap();
+        let mut expected = vec![Sent::Command(SET_DDRAM_ADDRESS | 0x42)];
+        expected.extend(data("42"));
+        expected.push(Sent::Data(0xdf));
+        assert_eq!(sent(&mut lcd), expected);
+
+        // Longer lines are cut at the edge.
+        lcd.write_line(1, "abcdefghijklmnopqrstuvwxyz").unwrap();
+        let sent = sent(&mut lcd);
+        assert_eq!(sent[0], Sent::Command(SET_DDRAM_ADDRESS | 0x40));
+        assert_eq!(sent[1..], data("abcdefghijklmnop")[..]);
+
+        assert!(matches!(
+            lcd.write_line(2, "x"),
+            Err(Lcd1602Error::NoSuchPosition { col: 0, row: 2 })
+        ));
+    }
+
+    #[test]
+    fn glyphs_and_the_cursor() {
+        let mut lcd = lcd();
+        lcd.define_glyph(1, &BAR_GLYPHS[2]).unwrap();
+        let mut expected = vec![Sent::Command(SET_CGRAM_ADDRESS | 8)];
+        expected.extend([0x1c; 8].map(Sent::Data));
+        assert_eq!(sent(&mut lcd), expected);
+
+        // Back in display RAM before the next character.
+        lcd.write_line(0, "\x01").unwrap();
+        assert_eq!(
+            sent(&mut lcd),
+            [Sent::Command(SET_DDRAM_ADDRESS), Sent::Data(0x01)]
+        );
+        assert!(matches!(
+            lcd.define_glyph(8, &BAR_GLYPHS[0]),
+            Err(Lcd1602Error::NoSuchSlot(8))
+        ));
+    }
+
+    #[test]
+    fn clear_resets_the_shadow() {
+        let mut lcd = lcd();
+        lcd.write_line(0, "Hi").unwrap();
+        lcd.clear().unwrap();
+        assert_eq!(sent(&mut lcd).last(), Some(&Sent::Command(CLEAR_DISPLAY)));
+        lcd.write_line(0, "Hi").unwrap();
+        assert_eq!(sent(&mut lcd), data("Hi"));
+    }
+}
@@ -0,0 +1,361 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::analog::{AnalogInput, Topology, RAIL_MARGIN};
+
+// R = R10 * (lux / 10)^-gamma, with R10 the resistance at 10 lux. Datasheets give a
+// range for both, so expect lux to be right within a factor of two at best.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LdrModel {
+    pub r10: f64,
+    pub gamma: f64,
+}
+
+impl LdrModel {
+    // GL5528, the part on the kit's module: 8-20 kΩ at 10 lux, gamma 0.7.
+    pub fn gl5528() -> Self {
+        LdrModel {
+            r10: 15_000.0,
+            gamma: 0.7,
+        }
+    }
+
+    pub fn lux(&self, resistance: f64) -> f64 {
+        if resistance.is_infinite() {
+            return 0.0;
+        }
+        10.0 * (self.r10 / resistance).powf(1.0 / self.gamma)
+    }
+
+    pub fn resistance(&self, lux: f64) -> f64 {
+        self.r10 * (lux / 10.0).powf(-self.gamma)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightLevel {
+    Day,
+    Night,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightEvent {
+    pub level: LightLevel,
+    pub lux: f64,
+    pub at: Instant,
+}
+
+// Day/night with hysteresis: night below `night_below`, day above `day_above`,
+// unchanged in between. A crossing has to last `min_hold` to count, so headlights or
+// a passing shadow do not flip it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayNightConfig {
+    pub night_below: f64,
+    pub day_above: f64,
+    pub min_hold: Duration,
+}
+
+impl Default for DayNightConfig {
+    fn default() -> Self {
+        DayNightConfig {
+            night_below: 10.0,
+            day_above: 50.0,
+            min_hold: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct DayNightMachine {
+    config: DayNightConfig,
+    level: Option<LightLevel>,
+    pending: Option<(LightLevel, Instant)>,
+}
+
+impl DayNightMachine {
+    pub fn new(config: DayNightConfig) -> Self {
+        DayNightMachine {
+            config,
+            level: None,
+            pending: None,
+        }
+    }
+
+    pub fn level(&self) -> Option<LightLevel> {
+        self.level
+    }
+
+    // The first reading sets the level straight away and reports it.
+    pub fn update(&mut self, lux: f64, now: Instant) -> Option<LightEvent> {
+        let wanted = if lux < self.config.night_below {
+            LightLevel::Night
+        } else if lux > self.config.day_above {
+            LightLevel::Day
+        } else {
+            self.pending = None;
+            return None;
+        };
+        if self.level == Some(wanted) {
+            self.pending = None;
+            return None;
+        }
+        if self.level.is_some() {
+            let since = match self.pending {
+                Some((level, since)) if level == wanted => since,
+                _ => {
+                    self.pending = Some((wanted, now));
+                    now
+                }
+            };
+            if now.duration_since(since) < self.config.min_hold {
+                return None;
+            }
+        }
+        self.level = Some(wanted);
+        self.pending = None;
+        Some(LightEvent {
+            level: wanted,
+            lux,
+            at: now,
+        })
+    }
+}
+
+pub struct LdrSensor<A: AnalogInput> {
+    adc: A,
+    channel: u8,
+    model: LdrModel,
+    topology: Topology,
+    series_resistor: f64,
+    supply_voltage: f64,
+    samples: u32,
+    machine: DayNightMachine,
+}
+
+impl<A: AnalogInput> LdrSensor<A> {
+    // `supply_voltage` feeds the divider, which need not be the ADC's reference.
+    // Defaults to the kit's module: LDR from the supply to the input, 10 kΩ to
+    // ground, so the voltage rises with light.
+    pub fn new(adc: A, channel: u8, model: LdrModel, supply_voltage: f64) -> Self {
+        LdrSensor {
+            adc,
+            channel,
+            model,
+            topology: Topology::HighSide,
+            series_resistor: 10_000.0,
+            supply_voltage,
+            samples: 4,
+            machine: DayNightMachine::new(DayNightConfig::default()),
+        }
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_series_resistor(mut self, ohms: f64) -> Self {
+        self.series_resistor = ohms;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn with_day_night(mut self, config: DayNightConfig) -> Self {
+        self.machine = DayNightMachine::new(config);
+        self
+    }
+
+    pub fn model(&self) -> LdrModel {
+        self.model
+    }
+
+    pub fn level(&self) -> Option<LightLevel> {
+        self.machine.level()
+    }
+
+    // LDR resistance in ohms. Readings at either rail are held just inside it, so
+    // full darkness or a saturated input gives the divider's limit, not 0 or inf.
+    pub fn resistance(&mut self) -> Result<f64, Box<dyn Error>> {
+        let mut sum = 0.0;
+        for _ in 0..self.samples {
+            sum += self.adc.read_voltage(self.channel)?;
+        }
+        let ratio =
+            (sum / self.samples as f64 / self.supply_voltage).clamp(RAIL_MARGIN, 1.0 - RAIL_MARGIN);
+        Ok(self.topology.resistance(ratio, self.series_resistor))
+    }
+
+    pub fn lux(&mut self) -> Result<f64, Box<dyn Error>> {
+        let resistance = self.resistance()?;
+        Ok(self.model.lux(resistance))
+    }
+
+    // Corrects R10 from a reading taken at a known light level, e.g. next to a lux
+    // meter, and returns the new value.
+    pub fn calibrate(&mut self, known_lux: f64) -> Result<f64, Box<dyn Error>> {
+        let resistance = self.resistance()?;
+        self.model.r10 = resistance * (known_lux / 10.0).powf(self.model.gamma);
+        Ok(self.model.r10)
+    }
+
+    // Reads once and returns the lux and a day/night change, if any.
+    pub fn poll(&mut self) -> Result<(f64, Option<LightEvent>), Box<dyn Error>> {
+        let lux = self.lux()?;
+        Ok((lux, self.machine.update(lux, Instant::now())))
+    }
+
+    // Calls back with every reading, at `period`.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        period: Duration,
+        mut callback: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(f64, Option<LightEvent>),
+    {
+        let mut next = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            let (lux, event) = self.poll()?;
+            callback(lux, event);
+            next += period;
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16-bit ADC with a 6.144 V full scale, like the ADS1115, reading one fixed count.
+    struct FixedAdc(u16);
+
+    impl AnalogInput for FixedAdc {
+        fn channels(&self) -> u8 {
+            4
+        }
+
+        fn resolution_bits(&self) -> u8 {
+            16
+        }
+
+        fn reference_voltage(&self) -> f64 {
+            6.144
+        }
+
+        fn read_raw(&mut self, _channel: u8) -> Result<u16, Box<dyn Error>> {
+            Ok(self.0)
+        }
+    }
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    fn level_of(event: Option<LightEvent>) -> Option<LightLevel> {
+        event.map(|e| e.level)
+    }
+
+    #[test]
+    fn first_reading_outside_the_band_sets_the_level() {
+        let start = Instant::now();
+        let mut machine = DayNightMachine::new(DayNightConfig::default());
+        // Inside the band there is nothing to go on yet.
+        assert_eq!(machine.update(30.0, start), None);
+        assert_eq!(machine.level(), None);
+        let event = machine.update(3.0, secs(start, 1)).unwrap();
+        assert_eq!(event.level, LightLevel::Night);
+        assert_eq!(event.lux, 3.0);
+        assert_eq!(event.at, secs(start, 1));
+        assert_eq!(machine.level(), Some(LightLevel::Night));
+    }
+
+    #[test]
+    fn the_band_between_thresholds_changes_nothing() {
+        let start = Instant::now();
+        let mut machine = DayNightMachine::new(DayNightConfig::default());
+        assert_eq!(
+            level_of(machine.update(100.0, start)),
+            Some(LightLevel::Day)
+        );
+        // Dusk settles in the band for an hour and it is still day.
+        for minute in 1..=60 {
+            assert_eq!(machine.update(20.0, secs(start, minute * 60)), None);
+        }
+        assert_eq!(machine.level(), Some(LightLevel::Day));
+        assert_eq!(machine.update(9.0, secs(start, 3700)), None);
+        assert_eq!(
+            level_of(machine.update(9.0, secs(start, 3705))),
+            Some(LightLevel::Night)
+        );
+        // And back up only once it clears the upper threshold.
+        assert_eq!(machine.update(45.0, secs(start, 3800)), None);
+        assert_eq!(machine.update(45.0, secs(start, 3900)), None);
+        assert_eq!(machine.update(51.0, secs(start, 3901)), None);
+        assert_eq!(
+            level_of(machine.update(51.0, secs(start, 3906))),
+            Some(LightLevel::Day)
+        );
+    }
+
+    #[test]
+    fn a_crossing_must_last_min_hold() {
+        let start = Instant::now();
+        let mut machine = DayNightMachine::new(DayNightConfig::default());
+        machine.update(2.0, start);
+        // Headlights for three seconds.
+        assert_eq!(machine.update(500.0, secs(start, 10)), None);
+        assert_eq!(machine.update(500.0, secs(start, 13)), None);
+        assert_eq!(machine.update(2.0, secs(start, 14)), None);
+        // The next flash starts its own count.
+        assert_eq!(machine.update(500.0, secs(start, 20)), None);
+        assert_eq!(machine.update(500.0, secs(start, 24)), None);
+        assert_eq!(machine.level(), Some(LightLevel::Night));
+        assert_eq!(
+            level_of(machine.update(500.0, secs(start, 25))),
+            Some(LightLevel::Day)
+        );
+    }
+
+    #[test]
+    fn dropping_into_the_band_resets_the_hold() {
+        let start = Instant::now();
+        let mut machine = DayNightMachine::new(DayNightConfig::default());
+        machine.update(100.0, start);
+        assert_eq!(machine.update(5.0, secs(start, 1)), None);
+        assert_eq!(machine.update(20.0, secs(start, 4)), None);
+        assert_eq!(machine.update(5.0, secs(start, 6)), None);
+        assert_eq!(machine.update(5.0, secs(start, 10)), None);
+        assert_eq!(
+            level_of(machine.update(5.0, secs(start, 11))),
+            Some(LightLevel::Night)
+        );
+    }
+
+    #[test]
+    fn ratio_is_taken_against_the_divider_supply() {
+        // 2.5 V from a 5 V supply: the LDR equals the 10 kΩ resistor.
+        let raw = (2.5 / 6.144 * u16::MAX as f64).round() as u16;
+        let mut ldr = LdrSensor::new(FixedAdc(raw), 0, LdrModel::gl5528(), 5.0);
+        let resistance = ldr.resistance().unwrap();
+        assert!((resistance - 10_000.0).abs() < 5.0, "{}", resistance);
+    }
+
+    #[test]
+    fn saturated_readings_stay_finite() {
+        let mut bright = LdrSensor::new(FixedAdc(u16::MAX), 0, LdrModel::gl5528(), 5.0);
+        let lux = bright.lux().unwrap();
+        assert!(lux.is_finite() && lux > 1000.0, "{}", lux);
+
+        let mut dark = LdrSensor::new(FixedAdc(0), 0, LdrModel::gl5528(), 5.0);
+        let lux = dark.lux().unwrap();
+        assert!(lux.is_finite() && lux < 0.1, "{}", lux);
+    }
+}
@@ -1,5 +1,33 @@
+pub mod adc0834;
+pub mod ads1115;
+pub mod analog;
+pub mod analog_pwm;
+pub mod binary_sensor;
+pub mod button;
+pub mod dht;
+pub mod ds18b20;
+pub mod gcode;
+pub mod hcsr04;
 pub mod input;
+pub mod ir_remote;
+pub mod joystick;
+pub mod keypad;
+pub mod lcd1602;
+pub mod ldr;
+pub mod mcp3x08;
+#[allow(clippy::upper_case_acronyms, dead_code)]
 pub mod output;
+pub mod passcode;
+pub mod pca9685;
+pub mod pir;
+pub mod pwm;
+pub mod relay;
+pub mod rotary_encoder;
+pub mod stepper;
+pub mod temperature;
+pub mod thermistor;
+pub mod timing;
+pub mod ws2812;
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -0,0 +1,132 @@
+use std::error::Error;
+
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+
+use crate::analog::{AnalogInput, NoSuchChannel};
+
+// Both parts are specified up to 1 MHz at 2.7 V.
+const SPI_CLOCK: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    // 10-bit, 8 channels
+    Mcp3008,
+    // 12-bit, 8 channels
+    Mcp3208,
+}
+
+// CS to CE0/CE1, CLK to SCLK, DIN to MOSI, DOUT to MISO.
+pub struct Mcp3x08 {
+    spi: Spi,
+    model: Model,
+    vref: f64,
+}
+
+impl Mcp3x08 {
+    pub fn new(
+        model: Model,
+        slave_select: SlaveSelect,
+        vref: f64,
+    ) -> Result<Self, rppal::spi::Error> {
+        let spi = Spi::new(Bus::Spi0, slave_select, SPI_CLOCK, Mode::Mode0)?;
+        Ok(Mcp3x08 { spi, model, vref })
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // Single-ended when `single` is set, otherwise the pair selected by `channel`:
+    // 0 is CH0+/CH1-, 1 is CH1+/CH0-, 2 is CH2+/CH3- and so on.
+    fn convert(&self, single: bool, channel: u8) -> Result<u16, rppal::spi::Error> {
+        let mut read = [0u8; 3];
+        self.spi
+            .transfer(&mut read, &command(self.model, single, channel))?;
+        Ok(decode(self.model, read))
+    }
+
+    pub fn read_differential(&mut self, pair: u8) -> Result<u16, Box<dyn Error>> {
+        if pair >= 8 {
+            return Err(Box::new(NoSuchChannel(pair)));
+        }
+        Ok(self.convert(false, pair)?)
+    }
+}
+
+fn command(model: Model, single: bool, channel: u8) -> [u8; 3] {
+    let sgl = single as u8;
+    match model {
+        // start bit, then SGL/DIFF and D2-D0 in the top nibble of the second byte
+        Model::Mcp3008 => [0x01, sgl << 7 | (channel & 0x07) << 4, 0],
+        // five leading zeros, start bit, SGL/DIFF, D2; D1-D0 at the top of the next byte
+        Model::Mcp3208 => [
+            0x04 | sgl << 1 | (channel >> 2) & 1,
+            (channel & 0x03) << 6,
+            0,
+        ],
+    }
+}
+
+// DOUT floats until the null bit, so only the bits after it count.
+fn decode(model: Model, read: [u8; 3]) -> u16 {
+    match model {
+        Model::Mcp3008 => ((read[1] as u16 & 0x03) << 8) | read[2] as u16,
+        Model::Mcp3208 => ((read[1] as u16 & 0x0f) << 8) | read[2] as u16,
+    }
+}
+
+impl AnalogInput for Mcp3x08 {
+    fn channels(&self) -> u8 {
+        8
+    }
+
+    fn resolution_bits(&self) -> u8 {
+        match self.model {
+            Model::Mcp3008 => 10,
+            Model::Mcp3208 => 12,
+        }
+    }
+
+    fn reference_voltage(&self) -> f64 {
+        self.vref
+    }
+
+    fn read_raw(&mut self, channel: u8) -> Result<u16, Box<dyn Error>> {
+        if channel >= self.channels() {
+            return Err(Box::new(NoSuchChannel(channel)));
+        }
+        Ok(self.convert(true, channel)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mcp3008_command_bits() {
+        assert_eq!(command(Model::Mcp3008, true, 0), [0x01, 0x80, 0]);
+        assert_eq!(command(Model::Mcp3008, true, 5), [0x01, 0xd0, 0]);
+        assert_eq!(command(Model::Mcp3008, true, 7), [0x01, 0xf0, 0]);
+        assert_eq!(command(Model::Mcp3008, false, 0), [0x01, 0x00, 0]);
+        assert_eq!(command(Model::Mcp3008, false, 3), [0x01, 0x30, 0]);
+    }
+
+    #[test]
+    fn mcp3208_command_bits() {
+        assert_eq!(command(Model::Mcp3208, true, 0), [0x06, 0x00, 0]);
+        assert_eq!(command(Model::Mcp3208, true, 3), [0x06, 0xc0, 0]);
+        assert_eq!(command(Model::Mcp3208, true, 4), [0x07, 0x00, 0]);
+        assert_eq!(command(Model::Mcp3208, true, 7), [0x07, 0xc0, 0]);
+        assert_eq!(command(Model::Mcp3208, false, 5), [0x05, 0x40, 0]);
+    }
+
+    #[test]
+    fn decode_ignores_the_floating_bits() {
+        assert_eq!(decode(Model::Mcp3008, [0xff, 0xfe, 0xff]), 0x2ff);
+        assert_eq!(decode(Model::Mcp3008, [0x00, 0x03, 0xff]), 1023);
+        assert_eq!(decode(Model::Mcp3008, [0x00, 0x01, 0x23]), 0x123);
+        assert_eq!(decode(Model::Mcp3208, [0xff, 0xfa, 0xbc]), 0xabc);
+        assert_eq!(decode(Model::Mcp3208, [0x00, 0x0f, 0xff]), 4095);
+    }
+}
@@ -1,12 +1,23 @@
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use rppal::gpio::{Gpio, OutputPin};
+use chrono::NaiveTime;
+use rppal::gpio::{Gpio, Level, OutputPin};
+use rppal::i2c::I2c;
 use rppal::system::DeviceInfo;
 
+use crate::gcode::{Command, Plotter, PlotterConfig};
+use crate::pca9685::{self, Pca9685};
+use crate::pwm::{Motor, PwmChannel, PwmLed, Servo};
+use crate::relay::{run_schedule, CronExpr, InterlockGroup, Relay, RelayError, Schedule};
+use crate::stepper::{Direction, MultiStepper, StepMode, Stepper};
+use crate::ws2812::{self, Ws2812};
+
 const GPIO24: u8 = 24;
 const GPIO23: u8 = 23;
 const GPIO18: u8 = 18;
@@ -40,27 +51,68 @@ pub fn rgb_led() -> Result<(), Box<dyn Error>> {
     const GPIO_LED_GREEN: u8 = 18;
     const GPIO_LED_BLUE: u8 = 27;
 
-    const DURATION: u64 = 100 * 100;
-
-    // Retrieve the GPIO pin and configure it as an output.
-    let mut pin1 = Gpio::new()?.get(GPIO_LED_RED)?.into_output();
-    let mut pin2 = Gpio::new()?.get(GPIO_LED_GREEN)?.into_output();
-    let mut pin3 = Gpio::new()?.get(GPIO_LED_BLUE)?.into_output();
-
-    let light_led = |pin: &mut OutputPin, palse_width: u64| {
-        pin.set_pwm(
-            Duration::from_micros(DURATION),
-            Duration::from_micros(palse_width),
-        )
-    };
+    let mut leds: Vec<_> = pwm_channels(&[GPIO_LED_RED, GPIO_LED_GREEN, GPIO_LED_BLUE], 1000.0)?
+        .into_iter()
+        .map(PwmLed::new)
+        .collect();
     loop {
         for flags in COLOR_FLAGS {
-            light_led(&mut pin1, (flags & 0b100) * 100)?;
-            light_led(&mut pin2, (flags & 0b010) * 100)?;
-            light_led(&mut pin3, (flags & 0b001) * 100)?;
+            for (i, led) in leds.iter_mut().enumerate() {
+                led.set_brightness((flags >> (2 - i) & 1) as f64)?;
+            }
             thread::sleep(Duration::from_millis(500));
         }
     }
+}
+
+// With PCA9685 set in the environment, the PWM demos use channels 0, 1, ... of a
+// PCA9685 on I2C1 at 0x40 instead of software PWM on `pins`. `frequency` is the
+// chip's, shared by all its channels.
+pub(crate) fn pwm_channels(
+    pins: &[u8],
+    frequency: f64,
+) -> Result<Vec<Box<dyn PwmChannel>>, Box<dyn Error>> {
+    if std::env::var_os("PCA9685").is_none() {
+        let gpio = Gpio::new()?;
+        return pins
+            .iter()
+            .map(|&pin| Ok(Box::new(gpio.get(pin)?.into_output()) as Box<dyn PwmChannel>))
+            .collect();
+    }
+    if pins.len() > pca9685::CHANNELS as usize {
+        return Err(format!(
+            "{} outputs asked for, a PCA9685 has {}",
+            pins.len(),
+            pca9685::CHANNELS
+        )
+        .into());
+    }
+    let mut pca = Pca9685::new(I2c::new()?, pca9685::DEFAULT_ADDRESS)?;
+    pca.set_frequency(frequency)?;
+    Ok(pca
+        .into_channels()
+        .into_iter()
+        .take(pins.len())
+        .map(|channel| Box::new(channel) as Box<dyn PwmChannel>)
+        .collect())
+}
+
+// An 8-LED WS2812B stick on MOSI, cycling a rainbow until Ctrl-C.
+pub fn led_strip() -> Result<(), Box<dyn Error>> {
+    const LED_COUNT: usize = 8;
+
+    let mut strip = Ws2812::new(LED_COUNT)?.with_brightness(0.2).with_gamma(2.8);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    strip.animate(&running, Duration::from_millis(20), |pixels, frame| {
+        ws2812::rainbow(pixels, frame * 2)
+    })?;
     Ok(())
 }
 
@@ -397,41 +449,38 @@ pub fn beep_passive_buzzer() -> Result<(), Box<dyn Error>> {
 }
 
 pub fn motor() -> Result<(), Box<dyn Error>> {
-    let mut pin_en1 = Gpio::new()?.get(GPIO22)?.into_output();
-    let mut pin_1a = Gpio::new()?.get(GPIO27)?.into_output();
-    let mut pin_2a = Gpio::new()?.get(GPIO17)?.into_output();
+    let enable = pwm_channels(&[GPIO22], 1000.0)?.remove(0);
+    let mut motor = Motor::new(enable, GPIO27, GPIO17)?;
 
-    pin_en1.set_high();
-    pin_1a.set_high();
-    pin_2a.set_low();
+    motor.set_speed(1.0)?;
     thread::sleep(Duration::from_secs(3));
-    pin_2a.set_high();
+    motor.set_speed(0.0)?;
     thread::sleep(Duration::from_secs(3));
-    pin_1a.set_low();
+    motor.set_speed(-1.0)?;
     thread::sleep(Duration::from_secs(3));
-    Ok(())
+    motor.set_speed(0.0)
 }
 
 pub fn servomotor() -> Result<(), Box<dyn Error>> {
-    const PERIOD_MS: u64 = 20;
     const PULSE_MIN_US: u64 = 500;
     const PULSE_NEUTRAL_US: u64 = 1500;
     const PULSE_MAX_US: u64 = 2500;
 
-    let mut pin_servo = Gpio::new()?.get(GPIO18)?.into_output();
+    let mut servo = Servo::new(pwm_channels(&[GPIO18], 50.0)?.remove(0));
     for i in PULSE_NEUTRAL_US..PULSE_MAX_US {
-        pin_servo.set_pwm(Duration::from_millis(PERIOD_MS), Duration::from_micros(i))?;
+        servo.set_pulse_width(Duration::from_micros(i))?;
         thread::sleep(Duration::from_millis(10));
     }
     for i in PULSE_MIN_US..PULSE_NEUTRAL_US {
-        pin_servo.set_pwm(Duration::from_millis(PERIOD_MS), Duration::from_micros(i))?;
+        servo.set_pulse_width(Duration::from_micros(i))?;
         thread::sleep(Duration::from_millis(10));
     }
     Ok(())
 }
 
 pub fn relay() -> Result<(), Box<dyn Error>> {
-    let mut base_pin = Gpio::new()?.get(GPIO17)?.into_output();
+    let mut relay = Relay::new(GPIO17, Level::High)?
+        .with_min_interval(Duration::from_millis(500), Duration::from_millis(500));
 
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
@@ -441,58 +490,77 @@ pub fn relay() -> Result<(), Box<dyn Error>> {
     .expect("Error setting Ctrl-C handler");
 
     while running.load(Ordering::SeqCst) {
-        println!("Relay Open");
-        base_pin.set_low();
-        thread::sleep(Duration::from_secs(1));
         println!("Relay Close");
-        base_pin.set_high();
+        relay.close()?;
+        thread::sleep(Duration::from_secs(1));
+        println!("Relay Open");
+        relay.open()?;
         thread::sleep(Duration::from_secs(1));
     }
-    println!("Relay Close");
-    base_pin.set_high();
+    println!("Relay Open ({} cycles)", relay.cycles());
+    Ok(())
+}
+
+pub fn relay_schedule() -> Result<(), Box<dyn Error>> {
+    // Two loads that must never run together, each on for its own part of the day.
+    let group = InterlockGroup::new();
+    let min_interval = Duration::from_secs(60);
+    let mut heater = Relay::new(GPIO17, Level::High)?
+        .with_min_interval(min_interval, min_interval)
+        .with_interlock(&group);
+    let mut pump = Relay::new(GPIO27, Level::High)?
+        .with_min_interval(min_interval, min_interval)
+        .with_interlock(&group);
+    let heater_schedule = Schedule::Daily(vec![(
+        NaiveTime::from_hms(6, 0, 0),
+        NaiveTime::from_hms(8, 0, 0),
+    )]);
+    let pump_schedule = Schedule::Cron {
+        on: CronExpr::parse("0 */2 * * *")?,
+        off: CronExpr::parse("15 */2 * * *")?,
+    };
+
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    let pump_running = running.clone();
+    let pump_thread = thread::spawn(move || -> Result<u64, RelayError> {
+        run_schedule(&mut pump, &pump_schedule, &pump_running)?;
+        Ok(pump.cycles())
+    });
+    run_schedule(&mut heater, &heater_schedule, &running)?;
+    let pump_cycles = pump_thread.join().expect("pump thread panicked")?;
+    println!(
+        "heater: {} cycles, pump: {} cycles",
+        heater.cycles(),
+        pump_cycles
+    );
     Ok(())
 }
 
 pub fn stepper_motor() -> Result<(), Box<dyn Error>> {
-    let mut pins = [
-        Gpio::new()?.get(GPIO18)?.into_output(),
-        Gpio::new()?.get(GPIO23)?.into_output(),
-        Gpio::new()?.get(GPIO24)?.into_output(),
-        Gpio::new()?.get(GPIO25)?.into_output(),
-    ];
-    let one_step = [
-        [true, false, false, false],
-        [false, true, false, false],
-        [false, false, true, false],
-        [false, false, false, true],
-    ];
-    let two_step = [
-        [true, true, false, false],
-        [false, true, true, false],
-        [false, false, true, true],
-        [true, false, false, true],
-    ];
-    let half_step = [
-        [true, false, false, false],
-        [true, true, false, false],
-        [false, true, false, false],
-        [false, true, true, false],
-        [false, false, true, false],
-        [false, false, true, true],
-        [false, false, false, true],
-        [true, false, false, true],
-    ];
+    let mut stepper = Stepper::new([GPIO18, GPIO23, GPIO24, GPIO25], StepMode::HalfStep)?;
 
     loop {
-        for step in half_step {
-            for i in 0..4 {
-                if step[i] {
-                    pins[i].set_high();
-                } else {
-                    pins[i].set_low();
-                }
-                thread::sleep(Duration::from_micros(1000));
-            }
-        }
+        stepper.step(Direction::Forward);
+        thread::sleep(Duration::from_micros(4000));
     }
 }
+
+pub fn pen_plotter() -> Result<(), Box<dyn Error>> {
+    let x = Stepper::new([GPIO18, GPIO23, GPIO24, GPIO25], StepMode::HalfStep)?;
+    let y = Stepper::new([GPIO17, GPIO27, GPIO22, SPIMOSI], StepMode::HalfStep)?;
+    let mut plotter = Plotter::new(MultiStepper::new(vec![x, y]), PlotterConfig::default());
+
+    // G-code is read from the file given as the first argument, or from stdin.
+    let result = match std::env::args().nth(1) {
+        Some(path) => plotter.run(BufReader::new(File::open(path)?)),
+        None => plotter.run(io::stdin().lock()),
+    };
+    plotter.execute(&Command::DisableMotors);
+    Ok(result?)
+}
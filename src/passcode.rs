@@ -0,0 +1,432 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+
+// PBKDF2 iterations, so that guessing codes from a stolen file is slow. Every entry is
+// hashed on each attempt, so this is kept to what a Pi does in a few milliseconds.
+const HASH_ROUNDS: u32 = 10_000;
+const MAX_DIGITS: usize = 16;
+
+#[derive(Debug)]
+pub enum CodeStoreError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for CodeStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodeStoreError::Io(e) => write!(f, "io error: {}", e),
+            CodeStoreError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for CodeStoreError {}
+
+impl From<std::io::Error> for CodeStoreError {
+    fn from(e: std::io::Error) -> CodeStoreError {
+        CodeStoreError::Io(e)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// PBKDF2-HMAC-SHA256 of the code.
+pub fn hash_code(code: &str, salt: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(code.as_bytes(), salt, HASH_ROUNDS, &mut hash);
+    hash
+}
+
+pub fn random_salt() -> Result<[u8; 16], std::io::Error> {
+    let mut salt = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut salt)?;
+    Ok(salt)
+}
+
+struct StoredCode {
+    name: String,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+// Passcodes kept as `name:salt:hash` lines, salt and hash in hex. Blank lines and lines
+// starting with `#` are skipped.
+pub struct CodeStore {
+    codes: Vec<StoredCode>,
+}
+
+impl CodeStore {
+    pub fn parse(text: &str) -> Result<Self, CodeStoreError> {
+        let mut codes = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_error = |message: &str| CodeStoreError::Parse {
+                line: i + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() != 3 {
+                return Err(parse_error("expected name:salt:hash"));
+            }
+            let salt = from_hex(fields[1]).ok_or_else(|| parse_error("salt is not hex"))?;
+            let hash = from_hex(fields[2]).ok_or_else(|| parse_error("hash is not hex"))?;
+            if hash.len() != 32 {
+                return Err(parse_error("hash is not 32 bytes"));
+            }
+            codes.push(StoredCode {
+                name: fields[0].to_string(),
+                salt,
+                hash,
+            });
+        }
+        Ok(CodeStore { codes })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CodeStoreError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Builds a line for the store file.
+    pub fn entry(name: &str, code: &str, salt: &[u8]) -> String {
+        format!(
+            "{}:{}:{}",
+            name,
+            to_hex(salt),
+            to_hex(&hash_code(code, salt))
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    // Returns the name of the matching code. Every entry is checked and compared in
+    // constant time, so the timing does not reveal which one matched.
+    pub fn verify(&self, code: &str) -> Option<&str> {
+        let mut found = None;
+        for stored in &self.codes {
+            let hash = hash_code(code, &stored.salt);
+            let diff = hash
+                .iter()
+                .zip(&stored.hash)
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+            if diff == 0 && found.is_none() {
+                found = Some(stored.name.as_str());
+            }
+        }
+        found
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasscodeEvent {
+    // A digit was buffered; carries how many are buffered now.
+    Digit(usize),
+    Cleared,
+    TimedOut,
+    Granted(String),
+    Denied { failures: u32 },
+    LockedOut(Duration),
+    // A key was pressed during a lockout.
+    Ignored,
+    LockoutEnded,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PasscodeConfig {
+    // Inactivity after which a partly entered code is discarded.
+    pub timeout: Duration,
+    // Failures allowed before the first lockout.
+    pub max_attempts: u32,
+    // The first lockout. Each further failure doubles it, up to `max_lockout`.
+    pub lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl Default for PasscodeConfig {
+    fn default() -> Self {
+        PasscodeConfig {
+            timeout: Duration::from_secs(10),
+            max_attempts: 3,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// PIN entry from keypad keys: digits are buffered, `*` clears and `#` submits. Time is
+// passed in by the caller, so the whole sequence can be scripted.
+pub struct PasscodeEntry {
+    store: CodeStore,
+    config: PasscodeConfig,
+    buffer: String,
+    last_key: Option<Instant>,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl PasscodeEntry {
+    pub fn new(store: CodeStore, config: PasscodeConfig) -> Self {
+        PasscodeEntry {
+            store,
+            config,
+            buffer: String::new(),
+            last_key: None,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| now < until)
+    }
+
+    pub fn key(&mut self, key: char, now: Instant) -> Vec<PasscodeEvent> {
+        let mut events = self.tick(now);
+        if self.locked_until.is_some() {
+            events.push(PasscodeEvent::Ignored);
+            return events;
+        }
+        self.last_key = Some(now);
+        match key {
+            '0'..='9' => {
+                if self.buffer.len() < MAX_DIGITS {
+                    self.buffer.push(key);
+                }
+                events.push(PasscodeEvent::Digit(self.buffer.len()));
+            }
+            '*' => {
+                self.buffer.clear();
+                events.push(PasscodeEvent::Cleared);
+            }
+            // An empty submit is a stray key press, not an attempt.
+            '#' if self.buffer.is_empty() => (),
+            '#' => {
+                let code = std::mem::take(&mut self.buffer);
+                events.extend(self.submit(&code, now));
+            }
+            _ => (),
+        }
+        events
+    }
+
+    // Handles the entry timeout and the end of a lockout. Call it regularly.
+    pub fn tick(&mut self, now: Instant) -> Vec<PasscodeEvent> {
+        let mut events = vec![];
+        if let Some(until) = self.locked_until {
+            if now >= until {
+                self.locked_until = None;
+                events.push(PasscodeEvent::LockoutEnded);
+            }
+        }
+        if let Some(last) = self.last_key {
+            if !self.buffer.is_empty() && now >= last + self.config.timeout {
+                self.buffer.clear();
+                self.last_key = None;
+                events.push(PasscodeEvent::TimedOut);
+            }
+        }
+        events
+    }
+
+    // Checks `code` against the store, counting failures towards a lockout.
+    fn submit(&mut self, code: &str, now: Instant) -> Vec<PasscodeEvent> {
+        if let Some(name) = self.store.verify(code) {
+            self.failures = 0;
+            return vec![PasscodeEvent::Granted(name.to_string())];
+        }
+        self.failures += 1;
+        let mut events = vec![PasscodeEvent::Denied {
+            failures: self.failures,
+        }];
+        if self.failures >= self.config.max_attempts {
+            let doublings = (self.failures - self.config.max_attempts).min(31);
+            let lockout = self
+                .config
+                .lockout
+                .checked_mul(1 << doublings)
+                .map_or(self.config.max_lockout, |d| d.min(self.config.max_lockout));
+            self.locked_until = Some(now + lockout);
+            events.push(PasscodeEvent::LockedOut(lockout));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> PasscodeEntry {
+        let text = CodeStore::entry("alice", "1234", b"salt");
+        PasscodeEntry::new(CodeStore::parse(&text).unwrap(), PasscodeConfig::default())
+    }
+
+    // Presses each key of `keys` a second apart and collects the events.
+    fn press(entry: &mut PasscodeEntry, keys: &str, start: Instant) -> Vec<PasscodeEvent> {
+        keys.chars()
+            .enumerate()
+            .flat_map(|(i, key)| entry.key(key, start + Duration::from_secs(i as u64)))
+            .filter(|event| !matches!(event, PasscodeEvent::Digit(_)))
+            .collect()
+    }
+
+    #[test]
+    fn hash_is_pbkdf2_hmac_sha256() {
+        // From Python's hashlib.pbkdf2_hmac("sha256", b"passwd", b"salt", 10000).
+        assert_eq!(
+            to_hex(&hash_code("passwd", b"salt")),
+            "891ba7f6f871dbadd932fa3b35a3a07054eadd85b47aa470399b3521aaa5b686"
+        );
+        assert_ne!(hash_code("1234", b"salt"), hash_code("1234", b"pepper"));
+    }
+
+    #[test]
+    fn correct_code_is_granted() {
+        let mut entry = entry();
+        let t0 = Instant::now();
+        assert_eq!(
+            press(&mut entry, "1234#", t0),
+            vec![PasscodeEvent::Granted("alice".to_string())]
+        );
+        assert_eq!(entry.failures(), 0);
+    }
+
+    #[test]
+    fn star_clears_the_buffer() {
+        let mut entry = entry();
+        let t0 = Instant::now();
+        assert_eq!(
+            press(&mut entry, "99*1234#", t0),
+            vec![
+                PasscodeEvent::Cleared,
+                PasscodeEvent::Granted("alice".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_submit_is_not_an_attempt() {
+        let mut entry = entry();
+        let t0 = Instant::now();
+        assert_eq!(press(&mut entry, "#####", t0), vec![]);
+        assert_eq!(entry.failures(), 0);
+        assert!(!entry.is_locked(t0 + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn failures_lock_out_with_backoff() {
+        let mut entry = entry();
+        let t0 = Instant::now();
+        assert_eq!(
+            press(&mut entry, "1#2#3#", t0),
+            vec![
+                PasscodeEvent::Denied { failures: 1 },
+                PasscodeEvent::Denied { failures: 2 },
+                PasscodeEvent::Denied { failures: 3 },
+                PasscodeEvent::LockedOut(Duration::from_secs(30)),
+            ]
+        );
+        // Locked from the third `#` at t0 + 5 s.
+        let locked_at = t0 + Duration::from_secs(5);
+        assert!(entry.is_locked(locked_at + Duration::from_secs(29)));
+        assert_eq!(
+            entry.key('1', locked_at + Duration::from_secs(10)),
+            vec![PasscodeEvent::Ignored]
+        );
+        assert_eq!(
+            entry.tick(locked_at + Duration::from_secs(30)),
+            vec![PasscodeEvent::LockoutEnded]
+        );
+
+        // The next failure doubles the lockout.
+        let t1 = locked_at + Duration::from_secs(31);
+        assert_eq!(
+            press(&mut entry, "9#", t1),
+            vec![
+                PasscodeEvent::Denied { failures: 4 },
+                PasscodeEvent::LockedOut(Duration::from_secs(60)),
+            ]
+        );
+        let t2 = t1 + Duration::from_secs(62);
+        assert_eq!(
+            press(&mut entry, "1234#", t2),
+            vec![
+                PasscodeEvent::LockoutEnded,
+                PasscodeEvent::Granted("alice".to_string())
+            ]
+        );
+        assert_eq!(entry.failures(), 0);
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let config = PasscodeConfig {
+            max_attempts: 1,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(100),
+            ..PasscodeConfig::default()
+        };
+        let store = CodeStore::parse(&CodeStore::entry("alice", "1234", b"salt")).unwrap();
+        let mut entry = PasscodeEntry::new(store, config);
+        let mut now = Instant::now();
+        let mut lockouts = vec![];
+        for _ in 0..4 {
+            for event in press(&mut entry, "0#", now) {
+                if let PasscodeEvent::LockedOut(d) = event {
+                    lockouts.push(d.as_secs());
+                }
+            }
+            now += Duration::from_secs(200);
+        }
+        assert_eq!(lockouts, vec![30, 60, 100, 100]);
+    }
+
+    #[test]
+    fn partial_entry_times_out() {
+        let mut entry = entry();
+        let t0 = Instant::now();
+        press(&mut entry, "12", t0);
+        assert_eq!(entry.tick(t0 + Duration::from_secs(10)), vec![]);
+        assert_eq!(
+            entry.tick(t0 + Duration::from_secs(11)),
+            vec![PasscodeEvent::TimedOut]
+        );
+        // The digits before the timeout are gone.
+        assert_eq!(
+            press(&mut entry, "34#", t0 + Duration::from_secs(20)),
+            vec![PasscodeEvent::Denied { failures: 1 }]
+        );
+    }
+}
@@ -0,0 +1,436 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::pwm::PwmChannel;
+
+// A0-A5 to GND, as on the usual 16-channel boards.
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+
+pub const CHANNELS: u8 = 16;
+// Counts per PWM period.
+pub const STEPS: u16 = 4096;
+
+// Nominal; real chips run anywhere from about 25 to 27 MHz, see
+// `with_oscillator_frequency`.
+const INTERNAL_OSCILLATOR: f64 = 25_000_000.0;
+
+const REG_MODE1: u8 = 0x00;
+const REG_MODE2: u8 = 0x01;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_ALL_LED_ON_L: u8 = 0xfa;
+const REG_PRE_SCALE: u8 = 0xfe;
+
+const MODE1_RESTART: u8 = 0x80;
+const MODE1_AUTO_INCREMENT: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+const MODE1_ALLCALL: u8 = 0x01;
+// Totem-pole outputs, for driving LEDs and servo signal lines directly.
+const MODE2_OUTDRV: u8 = 0x04;
+
+// Bit 12 of an ON or OFF count holds the output fully on or off.
+const FULL: u16 = 0x1000;
+
+// The oscillator needs this long to settle after leaving sleep.
+const WAKE_UP: Duration = Duration::from_micros(500);
+
+// PRE_SCALE for `frequency`, or `None` if the chip cannot run that fast or slow
+// (about 24 Hz to 1526 Hz at 25 MHz).
+pub fn prescale(oscillator: f64, frequency: f64) -> Option<u8> {
+    let value = (oscillator / (STEPS as f64 * frequency)).round() - 1.0;
+    if (3.0..=255.0).contains(&value) {
+        Some(value as u8)
+    } else {
+        None
+    }
+}
+
+// ON and OFF counts for `duty`, with the rising edge `offset` counts into the
+// period. 0.0 and 1.0 use the full-off and full-on bits, so there is no glitch.
+pub fn duty_counts(duty: f64, offset: u16) -> (u16, u16) {
+    if duty.is_nan() || duty <= 0.0 {
+        return (0, FULL);
+    }
+    if duty >= 1.0 {
+        return (FULL, 0);
+    }
+    let high = ((duty * STEPS as f64).round() as u16).clamp(1, STEPS - 1);
+    let on = offset % STEPS;
+    (on, (on + high) % STEPS)
+}
+
+#[derive(Debug)]
+pub enum Pca9685Error<E> {
+    I2cError(E),
+    NoSuchChannel(u8),
+    FrequencyOutOfRange(f64),
+}
+
+impl<E: fmt::Debug> fmt::Display for Pca9685Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pca9685Error::I2cError(e) => write!(f, "i2c error: {:?}", e),
+            Pca9685Error::NoSuchChannel(channel) => write!(f, "no channel {}", channel),
+            Pca9685Error::FrequencyOutOfRange(hz) => {
+                write!(f, "{} Hz is out of the PWM frequency range", hz)
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug> Error for Pca9685Error<E> {}
+
+// 16 channels of 12-bit PWM sharing one frequency. Works with any blocking I2C bus,
+// e.g. `rppal::i2c::I2c`. Servos need a separate 5-6 V supply on V+; a dozen of
+// them stalling at once can draw well over 10 A.
+pub struct Pca9685<I2C> {
+    i2c: I2C,
+    address: u8,
+    oscillator: f64,
+    frequency: f64,
+    offsets: [u16; CHANNELS as usize],
+}
+
+impl<I2C, E> Pca9685<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    // Starts at 50 Hz for servos, every channel off.
+    pub fn new(i2c: I2C, address: u8) -> Result<Self, Pca9685Error<E>> {
+        let mut pca = Pca9685 {
+            i2c,
+            address,
+            oscillator: INTERNAL_OSCILLATOR,
+            frequency: 0.0,
+            offsets: [0; CHANNELS as usize],
+        };
+        pca.write_register(REG_MODE2, MODE2_OUTDRV)?;
+        pca.set_all_off()?;
+        pca.write_register(REG_MODE1, MODE1_AUTO_INCREMENT | MODE1_ALLCALL)?;
+        thread::sleep(WAKE_UP);
+        pca.set_frequency(50.0)?;
+        Ok(pca)
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    // Measure a known pulse with a scope or logic analyser and correct the clock
+    // here; servo positions are off by as much as the oscillator is. Applies from
+    // the next `set_frequency`.
+    pub fn with_oscillator_frequency(mut self, hz: f64) -> Self {
+        self.oscillator = hz;
+        self
+    }
+
+    // Spreads the rising edges of the channels evenly over the period, so a row of
+    // servos or LEDs does not switch all at once.
+    pub fn with_staggered_offsets(mut self) -> Self {
+        for (channel, offset) in self.offsets.iter_mut().enumerate() {
+            *offset = channel as u16 * (STEPS / CHANNELS as u16);
+        }
+        self
+    }
+
+    // The frequency actually running, after rounding the prescaler.
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frequency)
+    }
+
+    // The prescaler only takes writes while the oscillator is asleep, so every
+    // output pauses for a moment.
+    pub fn set_frequency(&mut self, frequency: f64) -> Result<(), Pca9685Error<E>> {
+        let prescale = prescale(self.oscillator, frequency)
+            .ok_or(Pca9685Error::FrequencyOutOfRange(frequency))?;
+        let mode = self.read_register(REG_MODE1)? & !MODE1_RESTART;
+        self.write_register(REG_MODE1, mode | MODE1_SLEEP)?;
+        self.write_register(REG_PRE_SCALE, prescale)?;
+        self.write_register(REG_MODE1, mode)?;
+        thread::sleep(WAKE_UP);
+        self.write_register(REG_MODE1, mode | MODE1_RESTART)?;
+        self.frequency = self.oscillator / (STEPS as f64 * (prescale as f64 + 1.0));
+        Ok(())
+    }
+
+    // Where in the period, in counts, the channel's output goes high.
+    pub fn set_offset(&mut self, channel: u8, offset: u16) -> Result<(), Pca9685Error<E>> {
+        check_channel(channel)?;
+        self.offsets[channel as usize] = offset % STEPS;
+        Ok(())
+    }
+
+    // Raw ON and OFF counts, 0-4095, or with bit 12 set for full on or off.
+    pub fn set_counts(&mut self, channel: u8, on: u16, off: u16) -> Result<(), Pca9685Error<E>> {
+        check_channel(channel)?;
+        self.write_counts(REG_LED0_ON_L + 4 * channel, on, off)
+    }
+
+    // 0.0 (off) to 1.0 (on), starting at the channel's offset.
+    pub fn set_duty(&mut self, channel: u8, duty: f64) -> Result<(), Pca9685Error<E>> {
+        check_channel(channel)?;
+        let (on, off) = duty_counts(duty, self.offsets[channel as usize]);
+        self.set_counts(channel, on, off)
+    }
+
+    pub fn set_pulse_width(
+        &mut self,
+        channel: u8,
+        pulse_width: Duration,
+    ) -> Result<(), Pca9685Error<E>> {
+        let duty = pulse_width.as_secs_f64() * self.frequency;
+        self.set_duty(channel, duty)
+    }
+
+    pub fn set_all_off(&mut self) -> Result<(), Pca9685Error<E>> {
+        self.write_counts(REG_ALL_LED_ON_L, 0, FULL)
+    }
+
+    // Stops the oscillator; outputs go off and the chip draws next to nothing.
+    pub fn sleep(&mut self) -> Result<(), Pca9685Error<E>> {
+        let mode = self.read_register(REG_MODE1)?;
+        self.write_register(REG_MODE1, (mode & !MODE1_RESTART) | MODE1_SLEEP)
+    }
+
+    // Leaves sleep and resumes every channel where it was.
+    pub fn wake(&mut self) -> Result<(), Pca9685Error<E>> {
+        let mode = self.read_register(REG_MODE1)?;
+        // Writing RESTART back as 1 clears it; 0 is ignored.
+        self.write_register(REG_MODE1, mode & !(MODE1_SLEEP | MODE1_RESTART))?;
+        thread::sleep(WAKE_UP);
+        if mode & MODE1_RESTART != 0 {
+            self.write_register(REG_MODE1, (mode & !MODE1_SLEEP) | MODE1_RESTART)?;
+        }
+        Ok(())
+    }
+
+    // Hands the chip over to one `Pca9685Channel` per output, in channel order.
+    pub fn into_channels(self) -> Vec<Pca9685Channel<I2C>> {
+        let chip = Arc::new(Mutex::new(self));
+        (0..CHANNELS)
+            .map(|channel| Pca9685Channel {
+                chip: chip.clone(),
+                channel,
+            })
+            .collect()
+    }
+
+    fn write_counts(&mut self, register: u8, on: u16, off: u16) -> Result<(), Pca9685Error<E>> {
+        let [on_l, on_h] = on.to_le_bytes();
+        let [off_l, off_h] = off.to_le_bytes();
+        self.i2c
+            .write(self.address, &[register, on_l, on_h, off_l, off_h])
+            .map_err(Pca9685Error::I2cError)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Pca9685Error<E>> {
+        self.i2c
+            .write(self.address, &[register, value])
+            .map_err(Pca9685Error::I2cError)
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Pca9685Error<E>> {
+        let mut value = [0u8];
+        self.i2c
+            .write_read(self.address, &[register], &mut value)
+            .map_err(Pca9685Error::I2cError)?;
+        Ok(value[0])
+    }
+}
+
+fn check_channel<E>(channel: u8) -> Result<(), Pca9685Error<E>> {
+    if channel < CHANNELS {
+        Ok(())
+    } else {
+        Err(Pca9685Error::NoSuchChannel(channel))
+    }
+}
+
+// One output of a shared `Pca9685`, for `Servo`, `PwmLed`, `Motor` and anything else
+// taking a `PwmChannel`. The chip has a single frequency, set on the chip, and the
+// period asked for is ignored: `set_pwm` keeps the pulse width, so servos stay right
+// at any chip frequency with room for the pulse, and `set_pwm_frequency` keeps the
+// duty cycle.
+pub struct Pca9685Channel<I2C> {
+    chip: Arc<Mutex<Pca9685<I2C>>>,
+    channel: u8,
+}
+
+impl<I2C> Pca9685Channel<I2C> {
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    // For chip-wide settings such as the frequency or sleep.
+    pub fn chip(&self) -> &Arc<Mutex<Pca9685<I2C>>> {
+        &self.chip
+    }
+}
+
+impl<I2C, E> PwmChannel for Pca9685Channel<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    E: fmt::Debug + 'static,
+{
+    fn set_pwm(&mut self, _period: Duration, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        let mut chip = self.chip.lock().map_err(|_| "PCA9685 lock poisoned")?;
+        Ok(chip.set_pulse_width(self.channel, pulse_width)?)
+    }
+
+    fn set_pwm_frequency(
+        &mut self,
+        _frequency: f64,
+        duty_cycle: f64,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut chip = self.chip.lock().map_err(|_| "PCA9685 lock poisoned")?;
+        Ok(chip.set_duty(self.channel, duty_cycle)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records every write; every register reads back as `mode1`.
+    #[derive(Default)]
+    struct FakeBus {
+        writes: Vec<Vec<u8>>,
+        mode1: u8,
+    }
+
+    impl Write for FakeBus {
+        type Error = ();
+
+        fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), ()> {
+            self.writes.push(bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    impl WriteRead for FakeBus {
+        type Error = ();
+
+        fn write_read(&mut self, _address: u8, _bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            buffer.fill(self.mode1);
+            Ok(())
+        }
+    }
+
+    fn chip(mode1: u8) -> Pca9685<FakeBus> {
+        let mut pca = Pca9685::new(FakeBus::default(), DEFAULT_ADDRESS).unwrap();
+        pca.i2c.writes.clear();
+        pca.i2c.mode1 = mode1;
+        pca
+    }
+
+    #[test]
+    fn prescale_covers_the_chip_range() {
+        assert_eq!(prescale(INTERNAL_OSCILLATOR, 50.0), Some(121));
+        assert_eq!(prescale(INTERNAL_OSCILLATOR, 24.0), Some(253));
+        assert_eq!(prescale(INTERNAL_OSCILLATOR, 1526.0), Some(3));
+        assert_eq!(prescale(INTERNAL_OSCILLATOR, 20.0), None);
+        assert_eq!(prescale(INTERNAL_OSCILLATOR, 2000.0), None);
+        // A fast oscillator needs a larger prescaler for the same frequency.
+        assert_eq!(prescale(26_000_000.0, 50.0), Some(126));
+    }
+
+    #[test]
+    fn duty_counts_wrap_the_offset() {
+        assert_eq!(duty_counts(0.5, 0), (0, 2048));
+        assert_eq!(duty_counts(0.5, 3000), (3000, 952));
+        assert_eq!(duty_counts(0.25, STEPS + 100), (100, 1124));
+        // Anything above zero is at least one count, anything below one leaves one.
+        assert_eq!(duty_counts(0.00001, 0), (0, 1));
+        assert_eq!(duty_counts(0.99999, 0), (0, STEPS - 1));
+    }
+
+    #[test]
+    fn duty_counts_use_the_full_bits_at_the_ends() {
+        assert_eq!(duty_counts(0.0, 1000), (0, FULL));
+        assert_eq!(duty_counts(-1.0, 0), (0, FULL));
+        assert_eq!(duty_counts(f64::NAN, 0), (0, FULL));
+        assert_eq!(duty_counts(1.0, 1000), (FULL, 0));
+        assert_eq!(duty_counts(2.0, 0), (FULL, 0));
+    }
+
+    #[test]
+    fn set_frequency_writes_the_prescaler_asleep() {
+        let running = MODE1_RESTART | MODE1_AUTO_INCREMENT | MODE1_ALLCALL;
+        let mut pca = chip(running);
+        pca.set_frequency(50.0).unwrap();
+        let awake = MODE1_AUTO_INCREMENT | MODE1_ALLCALL;
+        assert_eq!(
+            pca.i2c.writes,
+            vec![
+                vec![REG_MODE1, awake | MODE1_SLEEP],
+                vec![REG_PRE_SCALE, 121],
+                vec![REG_MODE1, awake],
+                vec![REG_MODE1, awake | MODE1_RESTART],
+            ]
+        );
+        assert!((pca.frequency() - 25_000_000.0 / (4096.0 * 122.0)).abs() < 1e-9);
+
+        pca.i2c.writes.clear();
+        assert!(matches!(
+            pca.set_frequency(5000.0),
+            Err(Pca9685Error::FrequencyOutOfRange(_))
+        ));
+        assert!(pca.i2c.writes.is_empty());
+    }
+
+    #[test]
+    fn sleep_and_wake() {
+        let awake = MODE1_AUTO_INCREMENT | MODE1_ALLCALL;
+        let mut pca = chip(awake | MODE1_RESTART);
+        pca.sleep().unwrap();
+        assert_eq!(pca.i2c.writes, vec![vec![REG_MODE1, awake | MODE1_SLEEP]]);
+
+        // PWM was running when the chip went to sleep, so RESTART resumes it.
+        let mut pca = chip(awake | MODE1_SLEEP | MODE1_RESTART);
+        pca.wake().unwrap();
+        assert_eq!(
+            pca.i2c.writes,
+            vec![
+                vec![REG_MODE1, awake],
+                vec![REG_MODE1, awake | MODE1_RESTART],
+            ]
+        );
+
+        let mut pca = chip(awake | MODE1_SLEEP);
+        pca.wake().unwrap();
+        assert_eq!(pca.i2c.writes, vec![vec![REG_MODE1, awake]]);
+    }
+
+    #[test]
+    fn channel_keeps_the_pulse_width() {
+        let mut pca = Pca9685::new(FakeBus::default(), DEFAULT_ADDRESS).unwrap();
+        pca.set_frequency(100.0).unwrap();
+        let frequency = pca.frequency();
+        let mut channel = pca.into_channels().remove(3);
+
+        // A servo asks for 1.5 ms in a 20 ms period; at 100 Hz that is 15 %.
+        channel
+            .set_pwm(Duration::from_millis(20), Duration::from_micros(1500))
+            .unwrap();
+        let high = (0.0015 * frequency * STEPS as f64).round() as u16;
+        let last = channel
+            .chip()
+            .lock()
+            .unwrap()
+            .i2c
+            .writes
+            .last()
+            .unwrap()
+            .clone();
+        let [off_l, off_h] = high.to_le_bytes();
+        assert_eq!(last, [REG_LED0_ON_L + 12, 0, 0, off_l, off_h]);
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDateTime, Timelike};
+
+use crate::binary_sensor::{BinarySensor, BinarySensorConfig};
+use crate::button;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PirConfig {
+    // The HC-SR501 fires at random for up to a minute after power-up.
+    pub warm_up: Duration,
+    // How long the room stays occupied after the sensor's output drops.
+    pub hold: Duration,
+}
+
+impl Default for PirConfig {
+    fn default() -> Self {
+        PirConfig {
+            warm_up: Duration::from_secs(60),
+            hold: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OccupancyEvent {
+    // `vacant_for` is None for the first occupancy after warm-up.
+    Occupied {
+        at: Instant,
+        vacant_for: Option<Duration>,
+    },
+    Vacant {
+        at: Instant,
+        occupied_for: Duration,
+    },
+}
+
+pub struct OccupancyMachine {
+    config: PirConfig,
+    ready_at: Instant,
+    warmed_up: bool,
+    sensor_active: bool,
+    // Motion seen since the last tick, so a short trigger between ticks is not lost.
+    triggered_at: Option<Instant>,
+    released_at: Option<Instant>,
+    occupied_since: Option<Instant>,
+    vacant_since: Option<Instant>,
+}
+
+impl OccupancyMachine {
+    pub fn new(config: PirConfig, active: bool, now: Instant) -> Self {
+        OccupancyMachine {
+            config,
+            ready_at: now + config.warm_up,
+            warmed_up: false,
+            sensor_active: active,
+            triggered_at: None,
+            released_at: None,
+            occupied_since: None,
+            vacant_since: None,
+        }
+    }
+
+    pub fn is_warmed_up(&self) -> bool {
+        self.warmed_up
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        self.occupied_since.is_some()
+    }
+
+    // Feeds the debounced sensor output. Returns true for a trigger that counts as
+    // motion, i.e. a rising edge after warm-up.
+    pub fn edge(&mut self, active: bool, now: Instant) -> bool {
+        if active == self.sensor_active {
+            return false;
+        }
+        self.sensor_active = active;
+        if !active {
+            self.released_at = Some(now);
+            return false;
+        }
+        if now < self.ready_at {
+            return false;
+        }
+        self.triggered_at = Some(now);
+        true
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Option<OccupancyEvent> {
+        if now < self.ready_at {
+            return None;
+        }
+        self.warmed_up = true;
+        let triggered_at = self.triggered_at.take();
+        match self.occupied_since {
+            None => {
+                // Still active at the end of warm-up counts as motion from then on.
+                let at = match triggered_at {
+                    Some(at) => at,
+                    None if self.sensor_active => self.ready_at,
+                    None => return None,
+                };
+                self.occupied_since = Some(at);
+                Some(OccupancyEvent::Occupied {
+                    at,
+                    vacant_for: self.vacant_since.map(|v| at.saturating_duration_since(v)),
+                })
+            }
+            Some(since) => {
+                let vacate_at = self.vacate_at()?;
+                if now < vacate_at {
+                    return None;
+                }
+                self.occupied_since = None;
+                self.vacant_since = Some(vacate_at);
+                Some(OccupancyEvent::Vacant {
+                    at: vacate_at,
+                    occupied_for: vacate_at.saturating_duration_since(since),
+                })
+            }
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if !self.warmed_up {
+            return Some(self.ready_at);
+        }
+        if self.triggered_at.is_some() {
+            return self.triggered_at;
+        }
+        self.occupied_since.and(self.vacate_at())
+    }
+
+    fn vacate_at(&self) -> Option<Instant> {
+        if self.sensor_active {
+            return None;
+        }
+        Some(self.released_at? + self.config.hold)
+    }
+}
+
+// Triggers per wall-clock hour for the last `keep` hours that saw any.
+pub struct HourlyCounts {
+    hours: VecDeque<(NaiveDateTime, u32)>,
+    keep: usize,
+}
+
+impl HourlyCounts {
+    pub fn new(keep: usize) -> Self {
+        HourlyCounts {
+            hours: VecDeque::new(),
+            keep,
+        }
+    }
+
+    pub fn record(&mut self, at: NaiveDateTime) {
+        let hour = at
+            .date()
+            .and_hms_opt(at.hour(), 0, 0)
+            .expect("hour from a valid time");
+        match self.hours.back_mut() {
+            Some((last, count)) if *last == hour => *count += 1,
+            _ => {
+                self.hours.push_back((hour, 1));
+                if self.hours.len() > self.keep {
+                    self.hours.pop_front();
+                }
+            }
+        }
+    }
+
+    // Oldest first, keyed by the start of the hour.
+    pub fn counts(&self) -> impl Iterator<Item = &(NaiveDateTime, u32)> {
+        self.hours.iter()
+    }
+}
+
+pub struct PirSensor {
+    sensor: BinarySensor,
+    machine: OccupancyMachine,
+    counts: HourlyCounts,
+}
+
+impl PirSensor {
+    pub fn new(pin: u8, config: PirConfig) -> Result<Self, rppal::gpio::Error> {
+        let sensor = BinarySensor::new(pin, BinarySensorConfig::pir())?;
+        let active = sensor.is_active();
+        Ok(PirSensor {
+            sensor,
+            machine: OccupancyMachine::new(config, active, Instant::now()),
+            counts: HourlyCounts::new(24),
+        })
+    }
+
+    pub fn is_warmed_up(&self) -> bool {
+        self.machine.is_warmed_up()
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        self.machine.is_occupied()
+    }
+
+    pub fn counts(&self) -> &HourlyCounts {
+        &self.counts
+    }
+
+    // Waits for the next occupancy change. Returns `None` if `timeout` passes first.
+    pub fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<OccupancyEvent>, rppal::gpio::Error> {
+        let started = Instant::now();
+        loop {
+            let now = Instant::now();
+            let mut wait = self
+                .machine
+                .next_deadline()
+                .map(|d| d.saturating_duration_since(now));
+            if let Some(timeout) = timeout {
+                let left = timeout.saturating_sub(now - started);
+                wait = Some(wait.map_or(left, |w| w.min(left)));
+            }
+            if let Some(change) = self.sensor.poll(wait)? {
+                if self.machine.edge(change.active, change.at) {
+                    self.counts.record(Local::now().naive_local());
+                }
+            }
+            let event = self.machine.tick(Instant::now());
+            if event.is_some() || timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Ok(event);
+            }
+        }
+    }
+
+    // Calls `callback` with every occupancy change until `running` is cleared.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        mut callback: F,
+    ) -> Result<(), rppal::gpio::Error>
+    where
+        F: FnMut(OccupancyEvent, &HourlyCounts),
+    {
+        while running.load(Ordering::SeqCst) {
+            if let Some(event) = self.poll(Some(Duration::from_millis(100)))? {
+                callback(event, &self.counts);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(mut self) -> Receiver<OccupancyEvent> {
+        button::spawn_events("pir sensor", move |running, send| {
+            self.run(running, |event, _| send(event))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn triggers_during_warm_up_are_ignored() {
+        let t0 = Instant::now();
+        let mut machine = OccupancyMachine::new(PirConfig::default(), false, t0);
+        assert_eq!(machine.next_deadline(), Some(t0 + secs(60)));
+        assert!(!machine.edge(true, t0 + secs(10)));
+        assert_eq!(machine.tick(t0 + secs(10)), None);
+        assert!(!machine.edge(false, t0 + secs(20)));
+        assert_eq!(machine.tick(t0 + secs(60)), None);
+        assert!(machine.is_warmed_up());
+        assert!(!machine.is_occupied());
+
+        assert!(machine.edge(true, t0 + secs(70)));
+        assert_eq!(machine.next_deadline(), Some(t0 + secs(70)));
+        assert_eq!(
+            machine.tick(t0 + secs(70)),
+            Some(OccupancyEvent::Occupied {
+                at: t0 + secs(70),
+                vacant_for: None,
+            })
+        );
+    }
+
+    #[test]
+    fn active_at_the_end_of_warm_up_is_occupied() {
+        let t0 = Instant::now();
+        let mut machine = OccupancyMachine::new(PirConfig::default(), true, t0);
+        assert_eq!(
+            machine.tick(t0 + secs(61)),
+            Some(OccupancyEvent::Occupied {
+                at: t0 + secs(60),
+                vacant_for: None,
+            })
+        );
+    }
+
+    #[test]
+    fn retrigger_extends_the_hold() {
+        let t0 = Instant::now();
+        let mut machine = OccupancyMachine::new(PirConfig::default(), false, t0);
+        machine.tick(t0 + secs(60));
+        machine.edge(true, t0 + secs(70));
+        machine.tick(t0 + secs(70));
+        // Never vacant while the sensor is still active.
+        assert_eq!(machine.next_deadline(), None);
+        machine.edge(false, t0 + secs(75));
+        assert_eq!(machine.next_deadline(), Some(t0 + secs(375)));
+
+        assert!(machine.edge(true, t0 + secs(200)));
+        // Still the same occupancy.
+        assert_eq!(machine.tick(t0 + secs(200)), None);
+        machine.edge(false, t0 + secs(210));
+        assert_eq!(machine.tick(t0 + secs(400)), None);
+        assert_eq!(
+            machine.tick(t0 + secs(511)),
+            Some(OccupancyEvent::Vacant {
+                at: t0 + secs(510),
+                occupied_for: secs(440),
+            })
+        );
+        assert!(!machine.is_occupied());
+        assert_eq!(machine.next_deadline(), None);
+    }
+
+    #[test]
+    fn vacant_for_on_the_next_occupancy() {
+        let t0 = Instant::now();
+        let config = PirConfig {
+            warm_up: Duration::ZERO,
+            hold: secs(10),
+        };
+        let mut machine = OccupancyMachine::new(config, false, t0);
+        machine.edge(true, t0 + secs(1));
+        machine.tick(t0 + secs(1));
+        machine.edge(false, t0 + secs(2));
+        assert!(matches!(
+            machine.tick(t0 + secs(12)),
+            Some(OccupancyEvent::Vacant { .. })
+        ));
+        machine.edge(true, t0 + secs(100));
+        assert_eq!(
+            machine.tick(t0 + secs(100)),
+            Some(OccupancyEvent::Occupied {
+                at: t0 + secs(100),
+                vacant_for: Some(secs(88)),
+            })
+        );
+    }
+
+    #[test]
+    fn hourly_counts_roll_over() {
+        let at = |day: u32, h: u32, m: u32| {
+            NaiveDate::from_ymd_opt(2024, 3, day)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let mut counts = HourlyCounts::new(2);
+        counts.record(at(1, 10, 5));
+        counts.record(at(1, 10, 59));
+        assert_eq!(
+            counts.counts().copied().collect::<Vec<_>>(),
+            [(at(1, 10, 0), 2)]
+        );
+        counts.record(at(1, 11, 0));
+        counts.record(at(1, 23, 30));
+        // Only the last two hours that saw any triggers are kept.
+        assert_eq!(
+            counts.counts().copied().collect::<Vec<_>>(),
+            [(at(1, 11, 0), 1), (at(1, 23, 0), 1)]
+        );
+        // Same hour of the next day is a new hour.
+        counts.record(at(2, 23, 10));
+        assert_eq!(
+            counts.counts().copied().collect::<Vec<_>>(),
+            [(at(1, 23, 0), 1), (at(2, 23, 0), 1)]
+        );
+    }
+}
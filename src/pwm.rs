@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, OutputPin};
+
+// Anything that can produce a PWM signal: a GPIO pin with software PWM, the hardware
+// PWM peripheral, or a channel of an external PWM chip.
+pub trait PwmChannel {
+    fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), Box<dyn Error>>;
+
+    fn set_pwm_frequency(&mut self, frequency: f64, duty_cycle: f64) -> Result<(), Box<dyn Error>> {
+        if !(frequency.is_finite() && frequency > 0.0) {
+            return Err(format!("invalid PWM frequency {} Hz", frequency).into());
+        }
+        let period = Duration::from_secs_f64(1.0 / frequency);
+        self.set_pwm(period, period.mul_f64(duty_cycle.clamp(0.0, 1.0)))
+    }
+}
+
+impl<P: PwmChannel + ?Sized> PwmChannel for Box<P> {
+    fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        (**self).set_pwm(period, pulse_width)
+    }
+
+    fn set_pwm_frequency(&mut self, frequency: f64, duty_cycle: f64) -> Result<(), Box<dyn Error>> {
+        (**self).set_pwm_frequency(frequency, duty_cycle)
+    }
+}
+
+impl PwmChannel for OutputPin {
+    fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        Ok(OutputPin::set_pwm(self, period, pulse_width)?)
+    }
+
+    fn set_pwm_frequency(&mut self, frequency: f64, duty_cycle: f64) -> Result<(), Box<dyn Error>> {
+        Ok(OutputPin::set_pwm_frequency(self, frequency, duty_cycle)?)
+    }
+}
+
+// A device driven by a single value in its own units, e.g. brightness or angle.
+pub trait PwmOutput {
+    fn write(&mut self, value: f64) -> Result<(), Box<dyn Error>>;
+}
+
+pub struct PwmLed<P: PwmChannel> {
+    channel: P,
+    frequency: f64,
+}
+
+impl<P: PwmChannel> PwmLed<P> {
+    pub fn new(channel: P) -> Self {
+        PwmLed {
+            channel,
+            frequency: 2000.0,
+        }
+    }
+
+    // 0.0 (off) to 1.0 (full brightness).
+    pub fn set_brightness(&mut self, brightness: f64) -> Result<(), Box<dyn Error>> {
+        self.channel
+            .set_pwm_frequency(self.frequency, brightness.clamp(0.0, 1.0))
+    }
+}
+
+impl<P: PwmChannel> PwmOutput for PwmLed<P> {
+    fn write(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set_brightness(value)
+    }
+}
+
+pub struct Servo<P: PwmChannel> {
+    channel: P,
+    period: Duration,
+    pulse_min: Duration,
+    pulse_max: Duration,
+    max_angle: f64,
+}
+
+impl<P: PwmChannel> Servo<P> {
+    // The SG90 in the kit: 20 ms period, 0.5-2.5 ms pulses for 0-180 degrees.
+    pub fn new(channel: P) -> Self {
+        Servo {
+            channel,
+            period: Duration::from_millis(20),
+            pulse_min: Duration::from_micros(500),
+            pulse_max: Duration::from_micros(2500),
+            max_angle: 180.0,
+        }
+    }
+
+    pub fn with_range(mut self, pulse_min: Duration, pulse_max: Duration, max_angle: f64) -> Self {
+        self.pulse_min = pulse_min;
+        self.pulse_max = pulse_max;
+        self.max_angle = max_angle;
+        self
+    }
+
+    pub fn set_pulse_width(&mut self, pulse_width: Duration) -> Result<(), Box<dyn Error>> {
+        self.channel.set_pwm(self.period, pulse_width)
+    }
+
+    pub fn set_angle(&mut self, angle: f64) -> Result<(), Box<dyn Error>> {
+        let ratio = (angle / self.max_angle).clamp(0.0, 1.0);
+        let pulse = self.pulse_min + (self.pulse_max - self.pulse_min).mul_f64(ratio);
+        self.set_pulse_width(pulse)
+    }
+}
+
+impl<P: PwmChannel> PwmOutput for Servo<P> {
+    fn write(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set_angle(value)
+    }
+}
+
+// A DC motor on an L293D: PWM on the enable pin sets the speed, 1A/2A the direction.
+pub struct Motor<P: PwmChannel> {
+    enable: P,
+    pin_1a: OutputPin,
+    pin_2a: OutputPin,
+    frequency: f64,
+}
+
+impl<P: PwmChannel> Motor<P> {
+    pub fn new(enable: P, pin_1a: u8, pin_2a: u8) -> Result<Self, Box<dyn Error>> {
+        Ok(Motor {
+            enable,
+            pin_1a: Gpio::new()?.get(pin_1a)?.into_output_low(),
+            pin_2a: Gpio::new()?.get(pin_2a)?.into_output_low(),
+            frequency: 1000.0,
+        })
+    }
+
+    // -1.0 (full reverse) to 1.0 (full forward). 0.0 lets the motor coast.
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), Box<dyn Error>> {
+        let speed = speed.clamp(-1.0, 1.0);
+        if speed > 0.0 {
+            self.pin_1a.set_high();
+            self.pin_2a.set_low();
+        } else if speed < 0.0 {
+            self.pin_1a.set_low();
+            self.pin_2a.set_high();
+        } else {
+            self.pin_1a.set_low();
+            self.pin_2a.set_low();
+        }
+        self.enable.set_pwm_frequency(self.frequency, speed.abs())
+    }
+}
+
+impl<P: PwmChannel> PwmOutput for Motor<P> {
+    fn write(&mut self, value: f64) -> Result<(), Box<dyn Error>> {
+        self.set_speed(value)
+    }
+}
@@ -0,0 +1,461 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Timelike};
+use rppal::gpio::{Gpio, Level, OutputPin};
+
+#[derive(Debug)]
+pub enum RelayError {
+    GpioError(rppal::gpio::Error),
+    // The relay switched less than the minimum on/off time ago.
+    TooSoon(Duration),
+    // Another relay in the same interlock group is closed.
+    Interlocked(usize),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelayError::GpioError(e) => write!(f, "gpio error: {}", e),
+            RelayError::TooSoon(remaining) => {
+                write!(
+                    f,
+                    "minimum switching interval not reached ({:?} left)",
+                    remaining
+                )
+            }
+            RelayError::Interlocked(id) => write!(f, "interlocked: relay {} is closed", id),
+        }
+    }
+}
+
+impl Error for RelayError {}
+
+impl From<rppal::gpio::Error> for RelayError {
+    fn from(e: rppal::gpio::Error) -> RelayError {
+        RelayError::GpioError(e)
+    }
+}
+
+// Relays sharing a group may never be closed at the same time.
+#[derive(Clone, Default)]
+pub struct InterlockGroup {
+    closed: Arc<Mutex<Option<usize>>>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl InterlockGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn closed(&self) -> Option<usize> {
+        *self.closed.lock().unwrap()
+    }
+}
+
+pub struct Relay {
+    pin: OutputPin,
+    active_level: Level,
+    min_on: Duration,
+    min_off: Duration,
+    last_switch: Option<Instant>,
+    closed: bool,
+    cycles: u64,
+    interlock: Option<(InterlockGroup, usize)>,
+}
+
+impl Relay {
+    // The relay starts open.
+    pub fn new(pin: u8, active_level: Level) -> Result<Self, RelayError> {
+        // Set the inactive level as the pin becomes an output, so the relay cannot
+        // close for a moment at startup.
+        let pin = Gpio::new()?.get(pin)?;
+        let pin = match active_level {
+            Level::High => pin.into_output_low(),
+            Level::Low => pin.into_output_high(),
+        };
+        Ok(Relay {
+            pin,
+            active_level,
+            min_on: Duration::ZERO,
+            min_off: Duration::ZERO,
+            last_switch: None,
+            closed: false,
+            cycles: 0,
+            interlock: None,
+        })
+    }
+
+    pub fn with_min_interval(mut self, min_on: Duration, min_off: Duration) -> Self {
+        self.min_on = min_on;
+        self.min_off = min_off;
+        self
+    }
+
+    pub fn with_interlock(mut self, group: &InterlockGroup) -> Self {
+        let id = group.next_id.fetch_add(1, Ordering::SeqCst);
+        self.interlock = Some((group.clone(), id));
+        self
+    }
+
+    pub fn id(&self) -> Option<usize> {
+        self.interlock.as_ref().map(|(_, id)| *id)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Number of times the contacts have been closed.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn close(&mut self) -> Result<(), RelayError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.check_interval(self.min_off)?;
+        if let Some((group, id)) = &self.interlock {
+            let mut closed = group.closed.lock().unwrap();
+            match *closed {
+                Some(other) if other != *id => return Err(RelayError::Interlocked(other)),
+                _ => *closed = Some(*id),
+            }
+        }
+        self.pin.write(self.active_level);
+        self.closed = true;
+        self.cycles += 1;
+        self.last_switch = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn open(&mut self) -> Result<(), RelayError> {
+        if !self.closed {
+            return Ok(());
+        }
+        self.check_interval(self.min_on)?;
+        self.force_open();
+        Ok(())
+    }
+
+    pub fn set(&mut self, closed: bool) -> Result<(), RelayError> {
+        if closed {
+            self.close()
+        } else {
+            self.open()
+        }
+    }
+
+    fn check_interval(&self, min: Duration) -> Result<(), RelayError> {
+        if let Some(last) = self.last_switch {
+            let elapsed = last.elapsed();
+            if elapsed < min {
+                return Err(RelayError::TooSoon(min - elapsed));
+            }
+        }
+        Ok(())
+    }
+
+    fn force_open(&mut self) {
+        self.pin.write(!self.active_level);
+        self.closed = false;
+        self.last_switch = Some(Instant::now());
+        if let Some((group, id)) = &self.interlock {
+            let mut closed = group.closed.lock().unwrap();
+            if *closed == Some(*id) {
+                *closed = None;
+            }
+        }
+    }
+}
+
+impl Drop for Relay {
+    // Never leave a load switched on when the program exits.
+    fn drop(&mut self) {
+        if self.closed {
+            self.force_open();
+        }
+    }
+}
+
+// One field of a cron expression: `*`, `5`, `1-5`, `*/15`, `0-30/10` and comma lists.
+#[derive(Debug, Clone, PartialEq)]
+struct CronField {
+    values: u64,
+    // Anything but a leading `*`, which decides how day and weekday combine.
+    restricted: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut values = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .map_err(|_| format!("bad step in '{}'", part))?,
+                ),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(format!("zero step in '{}'", part));
+            }
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((a, b)) = range.split_once('-') {
+                (
+                    a.parse().map_err(|_| format!("bad value in '{}'", part))?,
+                    b.parse().map_err(|_| format!("bad value in '{}'", part))?,
+                )
+            } else {
+                let v = range
+                    .parse()
+                    .map_err(|_| format!("bad value in '{}'", part))?;
+                (v, v)
+            };
+            if start < min || end > max || start > end {
+                return Err(format!("'{}' is outside {}-{}", part, min, max));
+            }
+            for v in (start..=end).step_by(step as usize) {
+                values |= 1 << v;
+            }
+        }
+        Ok(CronField {
+            values,
+            restricted: !field.starts_with('*'),
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+// A five-field cron expression: minute hour day-of-month month day-of-week (0 or 7 =
+// Sunday).
+// As in cron, when both day fields are restricted a time matches either of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    minute: CronField,
+    hour: CronField,
+    day: CronField,
+    month: CronField,
+    weekday: CronField,
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        }
+        Ok(CronExpr {
+            minute: CronField::parse(fields[0], 0, 59)?,
+            hour: CronField::parse(fields[1], 0, 23)?,
+            day: CronField::parse(fields[2], 1, 31)?,
+            month: CronField::parse(fields[3], 1, 12)?,
+            weekday: {
+                // Both 0 and 7 are Sunday.
+                let mut weekday = CronField::parse(fields[4], 0, 7)?;
+                if weekday.matches(7) {
+                    weekday.values |= 1;
+                }
+                weekday
+            },
+        })
+    }
+
+    pub fn matches(&self, at: &NaiveDateTime) -> bool {
+        let day = self.day.matches(at.day());
+        let weekday = self.weekday.matches(at.weekday().num_days_from_sunday());
+        let day_matches = if self.day.restricted && self.weekday.restricted {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        self.minute.matches(at.minute())
+            && self.hour.matches(at.hour())
+            && self.month.matches(at.month())
+            && day_matches
+    }
+}
+
+pub enum Schedule {
+    // (on, off) windows within a day. A window whose off time is earlier than its
+    // on time runs past midnight.
+    Daily(Vec<(NaiveTime, NaiveTime)>),
+    // The relay closes in minutes matching `on` and opens in minutes matching `off`.
+    Cron { on: CronExpr, off: CronExpr },
+}
+
+impl Schedule {
+    // The state the relay should be in at `at`, or `None` to leave it as it is.
+    pub fn desired_state(&self, at: &NaiveDateTime) -> Option<bool> {
+        match self {
+            Schedule::Daily(windows) => {
+                let t = at.time();
+                Some(windows.iter().any(|(on, off)| {
+                    if on <= off {
+                        *on <= t && t < *off
+                    } else {
+                        t >= *on || t < *off
+                    }
+                }))
+            }
+            Schedule::Cron { on, off } => {
+                if off.matches(at) {
+                    Some(false)
+                } else if on.matches(at) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+// Drives `relay` from `schedule` until `running` is cleared. Switching that is refused
+// because of the minimum interval is retried on the next check. On the way out the
+// relay opens at once, whatever its minimum on time.
+pub fn run_schedule(
+    relay: &mut Relay,
+    schedule: &Schedule,
+    running: &AtomicBool,
+) -> Result<(), RelayError> {
+    // The relay holding this one open, so the refusal is reported once.
+    let mut interlocked = None;
+    while running.load(Ordering::SeqCst) {
+        let now = chrono::Local::now().naive_local();
+        if let Some(closed) = schedule.desired_state(&now) {
+            match relay.set(closed) {
+                Ok(()) => interlocked = None,
+                Err(RelayError::TooSoon(_)) => (),
+                Err(RelayError::Interlocked(other)) => {
+                    if interlocked != Some(other) {
+                        println!("relay held open by interlock with relay {}", other);
+                    }
+                    interlocked = Some(other);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    if relay.is_closed() {
+        relay.force_open();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn cron_fields() {
+        let cron = CronExpr::parse("*/15 8-17 * * 1-5").unwrap();
+        // 2024-01-08 was a Monday.
+        assert!(cron.matches(&at(2024, 1, 8, 8, 30)));
+        assert!(!cron.matches(&at(2024, 1, 8, 8, 31)));
+        assert!(!cron.matches(&at(2024, 1, 8, 18, 0)));
+        assert!(!cron.matches(&at(2024, 1, 7, 9, 0)));
+        assert!(CronExpr::parse("60 * * * *").is_err());
+        assert!(CronExpr::parse("*/0 * * * *").is_err());
+        assert!(CronExpr::parse("* * * *").is_err());
+    }
+
+    #[test]
+    fn cron_day_and_weekday_combine_like_cron() {
+        // The 1st of the month or any Monday.
+        let either = CronExpr::parse("0 12 1 * 1").unwrap();
+        assert!(either.matches(&at(2024, 2, 1, 12, 0)));
+        assert!(either.matches(&at(2024, 1, 8, 12, 0)));
+        assert!(!either.matches(&at(2024, 1, 9, 12, 0)));
+        // With one of them a star, only the other counts.
+        let day_only = CronExpr::parse("0 12 1 * *").unwrap();
+        assert!(day_only.matches(&at(2024, 2, 1, 12, 0)));
+        assert!(!day_only.matches(&at(2024, 1, 8, 12, 0)));
+        let weekday_only = CronExpr::parse("0 12 */1 * 1").unwrap();
+        assert!(weekday_only.matches(&at(2024, 1, 8, 12, 0)));
+        assert!(!weekday_only.matches(&at(2024, 2, 1, 12, 0)));
+    }
+
+    #[test]
+    fn cron_weekday_seven_is_sunday() {
+        // 2024-01-07 was a Sunday.
+        let sunday = CronExpr::parse("0 9 * * 7").unwrap();
+        assert!(sunday.matches(&at(2024, 1, 7, 9, 0)));
+        assert!(!sunday.matches(&at(2024, 1, 6, 9, 0)));
+        let weekend = CronExpr::parse("0 9 * * 6-7").unwrap();
+        assert!(weekend.matches(&at(2024, 1, 6, 9, 0)));
+        assert!(weekend.matches(&at(2024, 1, 7, 9, 0)));
+        assert!(!weekend.matches(&at(2024, 1, 8, 9, 0)));
+        assert!(CronExpr::parse("0 9 * * 8").is_err());
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn daily_windows() {
+        let schedule = Schedule::Daily(vec![(time(7, 0), time(8, 30)), (time(18, 0), time(22, 0))]);
+        let state = |hour, minute| schedule.desired_state(&at(2024, 1, 8, hour, minute));
+        assert_eq!(state(6, 59), Some(false));
+        assert_eq!(state(7, 0), Some(true));
+        assert_eq!(state(8, 29), Some(true));
+        assert_eq!(state(8, 30), Some(false));
+        assert_eq!(state(12, 0), Some(false));
+        assert_eq!(state(18, 0), Some(true));
+        assert_eq!(state(22, 0), Some(false));
+    }
+
+    #[test]
+    fn daily_window_spanning_midnight() {
+        let schedule = Schedule::Daily(vec![(time(22, 0), time(6, 0))]);
+        let state = |hour, minute| schedule.desired_state(&at(2024, 1, 8, hour, minute));
+        assert_eq!(state(21, 59), Some(false));
+        assert_eq!(state(22, 0), Some(true));
+        assert_eq!(state(23, 59), Some(true));
+        assert_eq!(state(0, 0), Some(true));
+        assert_eq!(state(5, 59), Some(true));
+        assert_eq!(state(6, 0), Some(false));
+        assert_eq!(state(12, 0), Some(false));
+        assert_eq!(
+            Schedule::Daily(vec![]).desired_state(&at(2024, 1, 8, 0, 0)),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn cron_schedule_leaves_other_minutes_alone() {
+        let schedule = Schedule::Cron {
+            on: CronExpr::parse("0 7 * * *").unwrap(),
+            off: CronExpr::parse("0 7,19 * * *").unwrap(),
+        };
+        assert_eq!(schedule.desired_state(&at(2024, 1, 8, 7, 0)), Some(false));
+        assert_eq!(schedule.desired_state(&at(2024, 1, 8, 19, 0)), Some(false));
+        assert_eq!(schedule.desired_state(&at(2024, 1, 8, 12, 0)), None);
+        let schedule = Schedule::Cron {
+            on: CronExpr::parse("0 7 * * *").unwrap(),
+            off: CronExpr::parse("0 19 * * *").unwrap(),
+        };
+        assert_eq!(schedule.desired_state(&at(2024, 1, 8, 7, 0)), Some(true));
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Level, Trigger};
+
+use crate::button::{self, ButtonConfig, ButtonEvent, ButtonMachine};
+
+// Indexed by the previous and the current A/B state as `prev << 2 | curr`. Both
+// lines changing at once cannot happen on a real turn, so those entries are 0 and
+// counted as invalid along with bounces that go nowhere.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderEvent {
+    // Clockwise. `steps` is 1 unless acceleration is on.
+    Increment { steps: u32, at: Instant },
+    Decrement { steps: u32, at: Instant },
+    Button(ButtonEvent),
+}
+
+// Turning faster than one detent per `slow` multiplies the steps by how much
+// faster, up to `max_steps` per detent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Acceleration {
+    pub slow: Duration,
+    pub max_steps: u32,
+}
+
+impl Default for Acceleration {
+    fn default() -> Self {
+        Acceleration {
+            slow: Duration::from_millis(100),
+            max_steps: 10,
+        }
+    }
+}
+
+// Decodes A/B levels into detents. Feed it every edge with both current levels.
+pub struct QuadratureMachine {
+    state: u8,
+    // The state at a detent, where the knob starts.
+    rest: u8,
+    count: i8,
+    // An invalid transition left `count` out of step with the detents.
+    resync: bool,
+    steps_per_detent: i8,
+    acceleration: Option<Acceleration>,
+    last_detent: Option<Instant>,
+    invalid: u32,
+}
+
+impl QuadratureMachine {
+    // The KY-040 passes through all four states between detents. `a` and `b` are
+    // taken as the levels at a detent.
+    pub fn new(a: bool, b: bool, steps_per_detent: u8) -> Self {
+        let state = (a as u8) << 1 | b as u8;
+        QuadratureMachine {
+            state,
+            rest: state,
+            count: 0,
+            resync: false,
+            steps_per_detent: steps_per_detent.clamp(1, 4) as i8,
+            acceleration: None,
+            last_detent: None,
+            invalid: 0,
+        }
+    }
+
+    pub fn with_acceleration(mut self, acceleration: Acceleration) -> Self {
+        self.acceleration = Some(acceleration);
+        self
+    }
+
+    // Transitions rejected so far, a rough measure of contact bounce or missed edges.
+    pub fn invalid_transitions(&self) -> u32 {
+        self.invalid
+    }
+
+    pub fn edge(&mut self, a: bool, b: bool, now: Instant) -> Option<EncoderEvent> {
+        let state = (a as u8) << 1 | b as u8;
+        if state == self.state {
+            return None;
+        }
+        let delta = TRANSITIONS[(self.state << 2 | state) as usize];
+        self.state = state;
+        if delta == 0 {
+            self.invalid += 1;
+            self.resync = true;
+        }
+        self.count += delta;
+        // Back at a detent after missed or bounced edges: whatever was counted on the
+        // way is not a whole detent, so start again from here.
+        if self.resync && self.at_detent() {
+            self.resync = false;
+            if self.count.abs() < self.steps_per_detent {
+                self.count = 0;
+                return None;
+            }
+        }
+        if self.count.abs() < self.steps_per_detent {
+            return None;
+        }
+        let clockwise = self.count > 0;
+        self.count = 0;
+
+        let steps = match (self.acceleration, self.last_detent) {
+            (Some(acc), Some(last)) => {
+                let interval = now.saturating_duration_since(last).as_secs_f64();
+                let speedup = acc.slow.as_secs_f64() / interval.max(1e-6);
+                (speedup.round() as u32).clamp(1, acc.max_steps.max(1))
+            }
+            _ => 1,
+        };
+        self.last_detent = Some(now);
+        Some(if clockwise {
+            EncoderEvent::Increment { steps, at: now }
+        } else {
+            EncoderEvent::Decrement { steps, at: now }
+        })
+    }
+
+    fn at_detent(&self) -> bool {
+        match self.steps_per_detent {
+            1 => true,
+            // Detents at the rest state and its opposite.
+            2 => self.state == self.rest || self.state == self.rest ^ 0b11,
+            _ => self.state == self.rest,
+        }
+    }
+}
+
+// CLK is A and DT is B on the KY-040, which has its own pull-ups on both. SW
+// switches to ground and uses the Pi's pull-up.
+pub struct RotaryEncoder {
+    gpio: Gpio,
+    a: InputPin,
+    b: InputPin,
+    switch: Option<(InputPin, Level, ButtonMachine)>,
+    machine: QuadratureMachine,
+    position: i64,
+}
+
+impl RotaryEncoder {
+    pub fn new(clk: u8, dt: u8, sw: Option<u8>) -> Result<Self, rppal::gpio::Error> {
+        let gpio = Gpio::new()?;
+        let mut a = gpio.get(clk)?.into_input();
+        let mut b = gpio.get(dt)?.into_input();
+        a.set_interrupt(Trigger::Both)?;
+        b.set_interrupt(Trigger::Both)?;
+        let switch = match sw {
+            Some(pin) => {
+                let config = ButtonConfig::default();
+                let mut pin = gpio.get(pin)?.into_input_pullup();
+                pin.set_interrupt(Trigger::Both)?;
+                let pressed = pin.read() == config.active_level;
+                Some((
+                    pin,
+                    config.active_level,
+                    ButtonMachine::new(config, pressed, Instant::now()),
+                ))
+            }
+            None => None,
+        };
+        let machine = QuadratureMachine::new(a.is_high(), b.is_high(), 4);
+        Ok(RotaryEncoder {
+            gpio,
+            a,
+            b,
+            switch,
+            machine,
+            position: 0,
+        })
+    }
+
+    pub fn with_acceleration(mut self, acceleration: Acceleration) -> Self {
+        self.machine = self.machine.with_acceleration(acceleration);
+        self
+    }
+
+    // Detents (times their steps) clockwise minus counter-clockwise since start.
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    pub fn invalid_transitions(&self) -> u32 {
+        self.machine.invalid_transitions()
+    }
+
+    // Waits for the next events. Returns an empty list if `timeout` passes first.
+    pub fn poll(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<EncoderEvent>, rppal::gpio::Error> {
+        let started = Instant::now();
+        loop {
+            let now = Instant::now();
+            let mut wait = self
+                .switch
+                .as_ref()
+                .and_then(|(_, _, machine)| machine.next_deadline())
+                .map(|d| d.saturating_duration_since(now));
+            if let Some(timeout) = timeout {
+                let left = timeout.saturating_sub(now - started);
+                wait = Some(wait.map_or(left, |w| w.min(left)));
+            }
+
+            let mut pins = vec![&self.a, &self.b];
+            if let Some((pin, _, _)) = &self.switch {
+                pins.push(pin);
+            }
+            let interrupt = self
+                .gpio
+                .poll_interrupts(&pins, false, wait)?
+                .map(|(pin, level)| (pin.pin(), level));
+
+            let mut events = vec![];
+            let now = Instant::now();
+            match interrupt {
+                Some((pin, _)) if pin == self.a.pin() || pin == self.b.pin() => {
+                    // The other line has not changed, so read both for the full state.
+                    if let Some(event) = self.machine.edge(self.a.is_high(), self.b.is_high(), now)
+                    {
+                        self.position += match event {
+                            EncoderEvent::Increment { steps, .. } => steps as i64,
+                            EncoderEvent::Decrement { steps, .. } => -(steps as i64),
+                            EncoderEvent::Button(_) => 0,
+                        };
+                        events.push(event);
+                    }
+                }
+                _ => {}
+            }
+            if let Some((pin, active_level, machine)) = &mut self.switch {
+                // Read the switch itself on every wake-up, as `Button` does: the level of a
+                // queued edge may be stale, and this catches any edge that was missed.
+                machine.edge(pin.read() == *active_level, now);
+                events.extend(machine.tick(now).into_iter().map(EncoderEvent::Button));
+            }
+            if !events.is_empty() || timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Ok(events);
+            }
+        }
+    }
+
+    // Calls `callback` with every event until `running` is cleared.
+    pub fn run<F>(
+        &mut self,
+        running: &AtomicBool,
+        mut callback: F,
+    ) -> Result<(), rppal::gpio::Error>
+    where
+        F: FnMut(EncoderEvent),
+    {
+        while running.load(Ordering::SeqCst) {
+            for event in self.poll(Some(Duration::from_millis(100)))? {
+                callback(event);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawn(mut self) -> Receiver<EncoderEvent> {
+        button::spawn_events("rotary encoder", move |running, send| {
+            self.run(running, send)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A/B states of the KY-040 for one detent, starting and ending at rest.
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(false, true), (false, false), (true, false), (true, true)];
+    const ANTICLOCKWISE: [(bool, bool); 4] =
+        [(true, false), (false, false), (false, true), (true, true)];
+
+    fn play(
+        machine: &mut QuadratureMachine,
+        states: &[(bool, bool)],
+        at: Instant,
+    ) -> Vec<EncoderEvent> {
+        states
+            .iter()
+            .filter_map(|&(a, b)| machine.edge(a, b, at))
+            .collect()
+    }
+
+    fn steps(events: &[EncoderEvent]) -> Vec<i64> {
+        events
+            .iter()
+            .map(|event| match *event {
+                EncoderEvent::Increment { steps, .. } => steps as i64,
+                EncoderEvent::Decrement { steps, .. } => -(steps as i64),
+                EncoderEvent::Button(_) => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn one_detent_each_way() {
+        let t0 = Instant::now();
+        let mut machine = QuadratureMachine::new(true, true, 4);
+        // Nothing until the cycle is complete.
+        assert!(play(&mut machine, &CLOCKWISE[..3], t0).is_empty());
+        assert_eq!(steps(&play(&mut machine, &CLOCKWISE[3..], t0)), [1]);
+        assert_eq!(steps(&play(&mut machine, &ANTICLOCKWISE, t0)), [-1]);
+        assert_eq!(machine.invalid_transitions(), 0);
+    }
+
+    #[test]
+    fn half_turn_and_back_is_nothing() {
+        let t0 = Instant::now();
+        let mut machine = QuadratureMachine::new(true, true, 4);
+        let there_and_back = [(false, true), (false, false), (false, true), (true, true)];
+        assert!(play(&mut machine, &there_and_back, t0).is_empty());
+        assert_eq!(steps(&play(&mut machine, &CLOCKWISE, t0)), [1]);
+    }
+
+    #[test]
+    fn repeated_level_is_ignored() {
+        let t0 = Instant::now();
+        let mut machine = QuadratureMachine::new(true, true, 4);
+        let bouncy = [
+            (false, true),
+            (false, true),
+            (false, false),
+            (true, false),
+            (true, true),
+        ];
+        assert_eq!(steps(&play(&mut machine, &bouncy, t0)), [1]);
+        assert_eq!(machine.invalid_transitions(), 0);
+    }
+
+    #[test]
+    fn resyncs_at_rest_after_invalid_transitions() {
+        let t0 = Instant::now();
+        let mut machine = QuadratureMachine::new(true, true, 4);
+        // Half a detent, then both lines jump back to rest.
+        assert!(play(&mut machine, &CLOCKWISE[..2], t0).is_empty());
+        assert!(play(&mut machine, &[(true, true)], t0).is_empty());
+        assert_eq!(machine.invalid_transitions(), 1);
+        // The half detent is forgotten: the next one ends at rest, not half way.
+        assert!(play(&mut machine, &CLOCKWISE[..3], t0).is_empty());
+        assert_eq!(steps(&play(&mut machine, &CLOCKWISE[3..], t0)), [1]);
+    }
+
+    #[test]
+    fn two_steps_per_detent() {
+        let t0 = Instant::now();
+        let mut machine = QuadratureMachine::new(true, true, 2);
+        assert_eq!(steps(&play(&mut machine, &CLOCKWISE, t0)), [1, 1]);
+        assert_eq!(steps(&play(&mut machine, &ANTICLOCKWISE, t0)), [-1, -1]);
+    }
+
+    #[test]
+    fn acceleration() {
+        let t0 = Instant::now();
+        let mut machine =
+            QuadratureMachine::new(true, true, 4).with_acceleration(Acceleration::default());
+        let mut detent = |at_ms: u64| {
+            steps(&play(
+                &mut machine,
+                &CLOCKWISE,
+                t0 + Duration::from_millis(at_ms),
+            ))
+        };
+        // The first detent has nothing to compare with.
+        assert_eq!(detent(0), [1]);
+        // Slower than 100 ms per detent, then 4x and 10x as fast.
+        assert_eq!(detent(300), [1]);
+        assert_eq!(detent(325), [4]);
+        assert_eq!(detent(335), [10]);
+        // Capped at `max_steps`.
+        assert_eq!(detent(336), [10]);
+    }
+}
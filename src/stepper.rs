@@ -0,0 +1,315 @@
+use std::thread;
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, OutputPin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    OneStep,
+    TwoStep,
+    HalfStep,
+}
+
+const ONE_STEP: [[bool; 4]; 4] = [
+    [true, false, false, false],
+    [false, true, false, false],
+    [false, false, true, false],
+    [false, false, false, true],
+];
+const TWO_STEP: [[bool; 4]; 4] = [
+    [true, true, false, false],
+    [false, true, true, false],
+    [false, false, true, true],
+    [true, false, false, true],
+];
+const HALF_STEP: [[bool; 4]; 8] = [
+    [true, false, false, false],
+    [true, true, false, false],
+    [false, true, false, false],
+    [false, true, true, false],
+    [false, false, true, false],
+    [false, false, true, true],
+    [false, false, false, true],
+    [true, false, false, true],
+];
+
+impl StepMode {
+    pub fn sequence(&self) -> &'static [[bool; 4]] {
+        match self {
+            StepMode::OneStep => &ONE_STEP,
+            StepMode::TwoStep => &TWO_STEP,
+            StepMode::HalfStep => &HALF_STEP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+pub struct Stepper {
+    pins: [OutputPin; 4],
+    mode: StepMode,
+    phase: usize,
+    position: i64,
+    enabled: bool,
+}
+
+impl Stepper {
+    pub fn new(pins: [u8; 4], mode: StepMode) -> Result<Self, rppal::gpio::Error> {
+        let gpio = Gpio::new()?;
+        let pins = [
+            gpio.get(pins[0])?.into_output_low(),
+            gpio.get(pins[1])?.into_output_low(),
+            gpio.get(pins[2])?.into_output_low(),
+            gpio.get(pins[3])?.into_output_low(),
+        ];
+        Ok(Stepper {
+            pins,
+            mode,
+            phase: 0,
+            position: 0,
+            enabled: false,
+        })
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: i64) {
+        self.position = position;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Energizes the coils for the current phase so the rotor holds its position.
+    pub fn enable(&mut self) {
+        self.enabled = true;
+        self.apply_phase();
+    }
+
+    // Releases all coils. The shaft can then be turned by hand and steps may be lost.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        for pin in &mut self.pins {
+            pin.set_low();
+        }
+    }
+
+    pub fn step(&mut self, direction: Direction) {
+        if !self.enabled {
+            self.enable();
+        }
+        let len = self.mode.sequence().len();
+        match direction {
+            Direction::Forward => {
+                self.phase = (self.phase + 1) % len;
+                self.position += 1;
+            }
+            Direction::Backward => {
+                self.phase = (self.phase + len - 1) % len;
+                self.position -= 1;
+            }
+        }
+        self.apply_phase();
+    }
+
+    fn apply_phase(&mut self) {
+        let step = self.mode.sequence()[self.phase];
+        for (pin, on) in self.pins.iter_mut().zip(step) {
+            if on {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+    }
+}
+
+// Bresenham-style interpolation over any number of axes. Each item holds, for every
+// axis, whether that axis has to take one step on this tick.
+pub struct LineSteps {
+    deltas: Vec<u64>,
+    errors: Vec<i64>,
+    major: u64,
+    tick: u64,
+}
+
+impl LineSteps {
+    pub fn new(deltas: &[i64]) -> Self {
+        let deltas: Vec<u64> = deltas.iter().map(|d| d.unsigned_abs()).collect();
+        let major = deltas.iter().copied().max().unwrap_or(0);
+        let errors = vec![major as i64 / 2; deltas.len()];
+        LineSteps {
+            deltas,
+            errors,
+            major,
+            tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.major
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.major == 0
+    }
+}
+
+impl Iterator for LineSteps {
+    type Item = Vec<bool>;
+
+    fn next(&mut self) -> Option<Vec<bool>> {
+        if self.tick >= self.major {
+            return None;
+        }
+        self.tick += 1;
+        let major = self.major as i64;
+        let steps = self
+            .deltas
+            .iter()
+            .zip(self.errors.iter_mut())
+            .map(|(delta, error)| {
+                *error -= *delta as i64;
+                if *error < 0 {
+                    *error += major;
+                    true
+                } else {
+                    false
+                }
+            })
+            .collect();
+        Some(steps)
+    }
+}
+
+// The direction each axis turns in, and the interpolated steps, to go from
+// `positions` to `targets`.
+fn plan_move(positions: &[i64], targets: &[i64]) -> (Vec<Direction>, LineSteps) {
+    let deltas: Vec<i64> = positions
+        .iter()
+        .zip(targets)
+        .map(|(position, target)| target - position)
+        .collect();
+    let directions = deltas
+        .iter()
+        .map(|d| {
+            if *d < 0 {
+                Direction::Backward
+            } else {
+                Direction::Forward
+            }
+        })
+        .collect();
+    (directions, LineSteps::new(&deltas))
+}
+
+pub struct MultiStepper {
+    axes: Vec<Stepper>,
+}
+
+impl MultiStepper {
+    pub fn new(axes: Vec<Stepper>) -> Self {
+        MultiStepper { axes }
+    }
+
+    pub fn axes(&self) -> &[Stepper] {
+        &self.axes
+    }
+
+    pub fn axes_mut(&mut self) -> &mut [Stepper] {
+        &mut self.axes
+    }
+
+    pub fn positions(&self) -> Vec<i64> {
+        self.axes.iter().map(|a| a.position()).collect()
+    }
+
+    pub fn enable(&mut self) {
+        for axis in &mut self.axes {
+            axis.enable();
+        }
+    }
+
+    pub fn disable(&mut self) {
+        for axis in &mut self.axes {
+            axis.disable();
+        }
+    }
+
+    // Moves all axes to `targets` (absolute step positions) so that they start and
+    // finish together. `total` is how long the whole move should take.
+    pub fn move_to(&mut self, targets: &[i64], total: Duration) {
+        assert_eq!(targets.len(), self.axes.len(), "one target per axis");
+        let (directions, line) = plan_move(&self.positions(), targets);
+        if line.is_empty() {
+            return;
+        }
+        // `len` is at least 1 here, and may not fit a `u32`.
+        let interval = total.div_f64(line.len() as f64);
+        for steps in line {
+            for ((axis, direction), step) in self.axes.iter_mut().zip(&directions).zip(steps) {
+                if step {
+                    axis.step(*direction);
+                }
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_counts(deltas: &[i64]) -> Vec<u64> {
+        let mut counts = vec![0; deltas.len()];
+        for steps in LineSteps::new(deltas) {
+            for (count, step) in counts.iter_mut().zip(steps) {
+                *count += step as u64;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn each_axis_takes_its_delta_in_steps() {
+        assert_eq!(step_counts(&[10, 4]), vec![10, 4]);
+        assert_eq!(step_counts(&[-3, 7, 0]), vec![3, 7, 0]);
+        assert_eq!(step_counts(&[5, -5]), vec![5, 5]);
+        assert_eq!(LineSteps::new(&[-3, 7, 0]).len(), 7);
+    }
+
+    #[test]
+    fn minor_axis_steps_are_spread_evenly() {
+        let minor: Vec<bool> = LineSteps::new(&[8, 2]).map(|steps| steps[1]).collect();
+        assert_eq!(
+            minor,
+            vec![false, false, true, false, false, false, true, false]
+        );
+        assert!(LineSteps::new(&[6, 6]).all(|steps| steps == vec![true, true]));
+    }
+
+    #[test]
+    fn no_motion_yields_no_ticks() {
+        assert!(LineSteps::new(&[0, 0]).is_empty());
+        assert_eq!(LineSteps::new(&[0, 0]).count(), 0);
+        assert!(LineSteps::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn plan_move_picks_directions_and_deltas() {
+        let (directions, line) = plan_move(&[10, -5], &[4, 0]);
+        assert_eq!(directions, vec![Direction::Backward, Direction::Forward]);
+        assert_eq!(line.len(), 6);
+        let (directions, line) = plan_move(&[3, 3], &[3, 3]);
+        assert_eq!(directions, vec![Direction::Forward, Direction::Forward]);
+        assert!(line.is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+const ZERO_CELSIUS: f64 = 273.15;
+
+// Kept in kelvin so conversions never stack rounding errors.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Temperature {
+    kelvin: f64,
+}
+
+impl Temperature {
+    pub fn from_kelvin(kelvin: f64) -> Self {
+        Temperature { kelvin }
+    }
+
+    pub fn from_celsius(celsius: f64) -> Self {
+        Temperature {
+            kelvin: celsius + ZERO_CELSIUS,
+        }
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f64) -> Self {
+        Self::from_celsius((fahrenheit - 32.0) / 1.8)
+    }
+
+    pub fn kelvin(&self) -> f64 {
+        self.kelvin
+    }
+
+    pub fn celsius(&self) -> f64 {
+        self.kelvin - ZERO_CELSIUS
+    }
+
+    pub fn fahrenheit(&self) -> f64 {
+        self.celsius() * 1.8 + 32.0
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match f.precision() {
+            Some(p) => write!(f, "{:.*}°C", p, self.celsius()),
+            None => write!(f, "{:.1}°C", self.celsius()),
+        }
+    }
+}
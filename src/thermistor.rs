@@ -0,0 +1,371 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::analog::{AnalogInput, Topology, RAIL_MARGIN};
+use crate::temperature::Temperature;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Model {
+    Beta {
+        // Resistance in ohms at `t0`.
+        r0: f64,
+        t0: Temperature,
+        beta: f64,
+    },
+    // 1/T = a + b ln(R) + c ln(R)^3, with T in kelvin.
+    SteinhartHart {
+        a: f64,
+        b: f64,
+        c: f64,
+    },
+    // (ohms, temperature) points from the datasheet, interpolated on ln(R).
+    // Resistances outside the table are out of range.
+    Table(Vec<(f64, Temperature)>),
+}
+
+impl Model {
+    // 10 kΩ at 25 °C, B=3950, as on the kit's module.
+    pub fn ntc_10k() -> Self {
+        Model::Beta {
+            r0: 10_000.0,
+            t0: Temperature::from_celsius(25.0),
+            beta: 3950.0,
+        }
+    }
+
+    pub fn ntc_100k() -> Self {
+        Model::Beta {
+            r0: 100_000.0,
+            t0: Temperature::from_celsius(25.0),
+            beta: 3950.0,
+        }
+    }
+
+    pub fn temperature(&self, resistance: f64) -> Option<Temperature> {
+        if !(resistance > 0.0 && resistance.is_finite()) {
+            return None;
+        }
+        match self {
+            Model::Beta { r0, t0, beta } => {
+                let inverse = 1.0 / t0.kelvin() + (resistance / r0).ln() / beta;
+                Some(Temperature::from_kelvin(1.0 / inverse))
+            }
+            Model::SteinhartHart { a, b, c } => {
+                let ln_r = resistance.ln();
+                Some(Temperature::from_kelvin(
+                    1.0 / (a + b * ln_r + c * ln_r.powi(3)),
+                ))
+            }
+            Model::Table(points) => {
+                let ln_r = resistance.ln();
+                points.windows(2).find_map(|pair| {
+                    let (r0, t0) = (pair[0].0.ln(), pair[0].1.kelvin());
+                    let (r1, t1) = (pair[1].0.ln(), pair[1].1.kelvin());
+                    if (r0 <= ln_r && ln_r <= r1) || (r1 <= ln_r && ln_r <= r0) {
+                        let t = if r0 == r1 {
+                            t0
+                        } else {
+                            t0 + (t1 - t0) * (ln_r - r0) / (r1 - r0)
+                        };
+                        Some(Temperature::from_kelvin(t))
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+        .filter(|t| t.kelvin() > 0.0 && t.kelvin().is_finite())
+    }
+}
+
+#[derive(Debug)]
+pub enum ThermistorError {
+    AdcError(Box<dyn Error>),
+    // The input sits at the rail that means an infinite thermistor resistance.
+    OpenCircuit,
+    // The input sits at the rail that means zero thermistor resistance.
+    ShortCircuit,
+    OutOfRange {
+        resistance: f64,
+        temperature: Option<Temperature>,
+    },
+}
+
+impl fmt::Display for ThermistorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThermistorError::AdcError(e) => write!(f, "adc error: {}", e),
+            ThermistorError::OpenCircuit => write!(f, "thermistor open circuit"),
+            ThermistorError::ShortCircuit => write!(f, "thermistor short circuit"),
+            ThermistorError::OutOfRange {
+                resistance,
+                temperature: Some(t),
+            } => write!(f, "{} ({:.0} Ω) is out of range", t, resistance),
+            ThermistorError::OutOfRange {
+                resistance,
+                temperature: None,
+            } => write!(f, "{:.0} Ω is outside the model", resistance),
+        }
+    }
+}
+
+impl Error for ThermistorError {}
+
+impl From<Box<dyn Error>> for ThermistorError {
+    fn from(e: Box<dyn Error>) -> Self {
+        ThermistorError::AdcError(e)
+    }
+}
+
+pub struct Thermistor<A: AnalogInput> {
+    adc: A,
+    channel: u8,
+    model: Model,
+    topology: Topology,
+    series_resistor: f64,
+    supply_voltage: f64,
+    samples: u32,
+    min: Temperature,
+    max: Temperature,
+}
+
+impl<A: AnalogInput> Thermistor<A> {
+    // `supply_voltage` feeds the divider. It is not necessarily the ADC's reference,
+    // e.g. the ADS1115 measures against its own. Defaults to the kit's wiring: low
+    // side with a 10 kΩ series resistor.
+    pub fn new(adc: A, channel: u8, model: Model, supply_voltage: f64) -> Self {
+        Thermistor {
+            adc,
+            channel,
+            model,
+            topology: Topology::LowSide,
+            series_resistor: 10_000.0,
+            supply_voltage,
+            samples: 1,
+            min: Temperature::from_celsius(-40.0),
+            max: Temperature::from_celsius(125.0),
+        }
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_series_resistor(mut self, ohms: f64) -> Self {
+        self.series_resistor = ohms;
+        self
+    }
+
+    // Averages this many conversions per reading.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    // Temperatures outside this range are reported as errors, as they usually mean
+    // a wiring or model problem rather than a real reading.
+    pub fn with_range(mut self, min: Temperature, max: Temperature) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn read_voltage(&mut self) -> Result<f64, ThermistorError> {
+        let mut sum = 0.0;
+        for _ in 0..self.samples {
+            sum += self.adc.read_voltage(self.channel)?;
+        }
+        Ok(sum / self.samples as f64)
+    }
+
+    // Thermistor resistance in ohms.
+    pub fn resistance(&mut self) -> Result<f64, ThermistorError> {
+        let ratio = self.read_voltage()? / self.supply_voltage;
+        let (open, short) = match self.topology {
+            Topology::LowSide => (ratio >= 1.0 - RAIL_MARGIN, ratio <= RAIL_MARGIN),
+            Topology::HighSide => (ratio <= RAIL_MARGIN, ratio >= 1.0 - RAIL_MARGIN),
+        };
+        if open {
+            return Err(ThermistorError::OpenCircuit);
+        }
+        if short {
+            return Err(ThermistorError::ShortCircuit);
+        }
+        Ok(self.topology.resistance(ratio, self.series_resistor))
+    }
+
+    pub fn read(&mut self) -> Result<Temperature, ThermistorError> {
+        let resistance = self.resistance()?;
+        match self.model.temperature(resistance) {
+            Some(t) if self.min <= t && t <= self.max => Ok(t),
+            temperature => Err(ThermistorError::OutOfRange {
+                resistance,
+                temperature,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads back a fixed voltage on every channel.
+    struct FixedAdc(f64);
+
+    impl AnalogInput for FixedAdc {
+        fn channels(&self) -> u8 {
+            1
+        }
+
+        fn resolution_bits(&self) -> u8 {
+            16
+        }
+
+        fn reference_voltage(&self) -> f64 {
+            5.0
+        }
+
+        fn read_raw(&mut self, _channel: u8) -> Result<u16, Box<dyn Error>> {
+            Ok((self.0 / 5.0 * self.max_count() as f64).round() as u16)
+        }
+
+        fn read_voltage(&mut self, _channel: u8) -> Result<f64, Box<dyn Error>> {
+            Ok(self.0)
+        }
+    }
+
+    fn celsius(model: &Model, resistance: f64) -> f64 {
+        model.temperature(resistance).unwrap().celsius()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    // The voltage a low side divider with 10 kΩ on a 3.3 V supply gives for `ohms`.
+    fn low_side(ohms: f64) -> FixedAdc {
+        FixedAdc(3.3 * ohms / (ohms + 10_000.0))
+    }
+
+    #[test]
+    fn beta_model() {
+        let model = Model::ntc_10k();
+        assert_close(celsius(&model, 10_000.0), 25.0, 1e-9);
+        assert_close(celsius(&model, 33_620.6), 0.0, 1e-3);
+        assert_close(celsius(&model, 3_588.18), 50.0, 1e-3);
+        assert_close(celsius(&Model::ntc_100k(), 100_000.0), 25.0, 1e-9);
+    }
+
+    #[test]
+    fn steinhart_hart_model() {
+        let model = Model::SteinhartHart {
+            a: 1.009249522e-3,
+            b: 2.378405444e-4,
+            c: 2.019202697e-7,
+        };
+        assert_close(celsius(&model, 10_000.0), 24.6813, 1e-3);
+        assert_close(celsius(&model, 3_588.18), 52.9219, 1e-3);
+    }
+
+    #[test]
+    fn table_model() {
+        let points = vec![
+            (33_620.0, Temperature::from_celsius(0.0)),
+            (10_000.0, Temperature::from_celsius(25.0)),
+            (3_588.0, Temperature::from_celsius(50.0)),
+        ];
+        let model = Model::Table(points.clone());
+        assert_close(celsius(&model, 10_000.0), 25.0, 1e-9);
+        assert_close(celsius(&model, 3_588.0), 50.0, 1e-9);
+        // Half way on ln(R) is half way in temperature.
+        let middle = (10_000.0f64 * 3_588.0).sqrt();
+        assert_close(celsius(&model, middle), 37.5, 1e-9);
+        assert_eq!(model.temperature(40_000.0), None);
+        assert_eq!(model.temperature(1_000.0), None);
+
+        // The order of the points does not matter.
+        let reversed = Model::Table(points.into_iter().rev().collect());
+        assert_close(celsius(&reversed, middle), 37.5, 1e-9);
+    }
+
+    #[test]
+    fn impossible_resistances() {
+        let model = Model::ntc_10k();
+        for ohms in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+            assert_eq!(model.temperature(ohms), None, "{}", ohms);
+        }
+    }
+
+    #[test]
+    fn reads_through_the_divider() {
+        let mut thermistor = Thermistor::new(low_side(10_000.0), 0, Model::ntc_10k(), 3.3);
+        assert_close(thermistor.resistance().unwrap(), 10_000.0, 1e-6);
+        assert_close(thermistor.read().unwrap().celsius(), 25.0, 1e-6);
+
+        let mut thermistor =
+            Thermistor::new(low_side(3_588.18), 0, Model::ntc_10k(), 3.3).with_samples(4);
+        assert_close(thermistor.read().unwrap().celsius(), 50.0, 1e-3);
+    }
+
+    #[test]
+    fn open_and_short_circuits() {
+        let mut open = Thermistor::new(FixedAdc(3.3), 0, Model::ntc_10k(), 3.3);
+        assert!(matches!(open.read(), Err(ThermistorError::OpenCircuit)));
+        let mut short = Thermistor::new(FixedAdc(0.0), 0, Model::ntc_10k(), 3.3);
+        assert!(matches!(short.read(), Err(ThermistorError::ShortCircuit)));
+
+        // The other way up, the rails swap meaning.
+        let mut open = Thermistor::new(FixedAdc(0.0), 0, Model::ntc_10k(), 3.3)
+            .with_topology(Topology::HighSide);
+        assert!(matches!(open.read(), Err(ThermistorError::OpenCircuit)));
+        let mut short = Thermistor::new(FixedAdc(3.3), 0, Model::ntc_10k(), 3.3)
+            .with_topology(Topology::HighSide);
+        assert!(matches!(short.read(), Err(ThermistorError::ShortCircuit)));
+    }
+
+    #[test]
+    fn out_of_range() {
+        // About 150 °C, past the default 125 °C.
+        let mut hot = Thermistor::new(low_side(199.7), 0, Model::ntc_10k(), 3.3);
+        match hot.read() {
+            Err(ThermistorError::OutOfRange {
+                resistance,
+                temperature: Some(t),
+            }) => {
+                assert_close(resistance, 199.7, 0.1);
+                assert_close(t.celsius(), 150.0, 0.1);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let mut narrow = Thermistor::new(low_side(10_000.0), 0, Model::ntc_10k(), 3.3).with_range(
+            Temperature::from_celsius(30.0),
+            Temperature::from_celsius(40.0),
+        );
+        assert!(matches!(
+            narrow.read(),
+            Err(ThermistorError::OutOfRange { .. })
+        ));
+
+        // Outside a table there is no temperature at all.
+        let table = Model::Table(vec![
+            (10_000.0, Temperature::from_celsius(25.0)),
+            (3_588.0, Temperature::from_celsius(50.0)),
+        ]);
+        let mut cold = Thermistor::new(low_side(33_620.0), 0, table, 3.3);
+        assert!(matches!(
+            cold.read(),
+            Err(ThermistorError::OutOfRange {
+                temperature: None,
+                ..
+            })
+        ));
+    }
+}
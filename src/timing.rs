@@ -0,0 +1,226 @@
+use std::hint;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::Level;
+
+// `thread::sleep` overshoots by tens of microseconds or more, so anything shorter
+// than this is spun out, and longer delays sleep only up to this much before the end.
+const SPIN_MARGIN: Duration = Duration::from_micros(200);
+
+// The cost of one `Instant::now()`, measured once. A delay ends this much early so
+// that the call which notices it has ended lands on time.
+fn clock_overhead() -> Duration {
+    static OVERHEAD: OnceLock<Duration> = OnceLock::new();
+    *OVERHEAD.get_or_init(|| {
+        let mut samples: Vec<Duration> = (0..101)
+            .map(|_| {
+                let start = Instant::now();
+                Instant::now() - start
+            })
+            .collect();
+        samples.sort();
+        samples[samples.len() / 2]
+    })
+}
+
+// Waits for `duration`, busy-waiting the last part for microsecond accuracy.
+pub fn delay(duration: Duration) {
+    let start = Instant::now();
+    if duration > SPIN_MARGIN {
+        thread::sleep(duration - SPIN_MARGIN);
+    }
+    let end = start + duration.saturating_sub(clock_overhead());
+    while Instant::now() < end {
+        hint::spin_loop();
+    }
+}
+
+pub fn delay_us(micros: u64) {
+    delay(Duration::from_micros(micros));
+}
+
+// Polls `read` until it returns `level` and returns how long that took, or None if it
+// did not happen within `timeout`.
+pub fn wait_for_level<F>(mut read: F, level: Level, timeout: Duration) -> Option<Duration>
+where
+    F: FnMut() -> Level,
+{
+    let start = Instant::now();
+    loop {
+        if read() == level {
+            return Some(start.elapsed());
+        }
+        if start.elapsed() > timeout {
+            return None;
+        }
+        hint::spin_loop();
+    }
+}
+
+// Waits up to `timeout` for a pulse at `level` to start, then returns its width. The
+// pulse itself may also last up to `timeout`.
+pub fn pulse_width<F>(mut read: F, level: Level, timeout: Duration) -> Option<Duration>
+where
+    F: FnMut() -> Level,
+{
+    let other = if level == Level::High {
+        Level::Low
+    } else {
+        Level::High
+    };
+    wait_for_level(&mut read, level, timeout)?;
+    wait_for_level(&mut read, other, timeout)
+}
+
+// SCHED_FIFO with the given priority (1-99), optionally pinned to one CPU, so the
+// kernel does not preempt a bit-banged transfer halfway. Needs root or CAP_SYS_NICE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Realtime {
+    pub priority: i32,
+    pub cpu: Option<usize>,
+}
+
+impl Default for Realtime {
+    fn default() -> Self {
+        Realtime {
+            priority: 50,
+            cpu: None,
+        }
+    }
+}
+
+impl Realtime {
+    // Applies to the calling thread until the guard is dropped.
+    pub fn enter(&self) -> io::Result<RealtimeGuard> {
+        if self
+            .cpu
+            .is_some_and(|cpu| cpu >= libc::CPU_SETSIZE as usize)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cpu number beyond CPU_SETSIZE",
+            ));
+        }
+        // SAFETY: plain syscalls on the calling thread with properly sized buffers.
+        unsafe {
+            let policy = libc::sched_getscheduler(0);
+            if policy < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut param: libc::sched_param = mem::zeroed();
+            if libc::sched_getparam(0, &mut param) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fifo = libc::sched_param {
+                sched_priority: self.priority,
+            };
+            if libc::sched_setscheduler(0, libc::SCHED_FIFO, &fifo) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut guard = RealtimeGuard {
+                policy,
+                param,
+                affinity: None,
+                _not_send: PhantomData,
+            };
+
+            if let Some(cpu) = self.cpu {
+                let size = mem::size_of::<libc::cpu_set_t>();
+                let mut previous: libc::cpu_set_t = mem::zeroed();
+                if libc::sched_getaffinity(0, size, &mut previous) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut set: libc::cpu_set_t = mem::zeroed();
+                libc::CPU_SET(cpu, &mut set);
+                if libc::sched_setaffinity(0, size, &set) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                guard.affinity = Some(previous);
+            }
+            Ok(guard)
+        }
+    }
+}
+
+// Puts back the scheduling policy and CPU affinity the thread had before. Tied to
+// the thread it was made on.
+pub struct RealtimeGuard {
+    policy: libc::c_int,
+    param: libc::sched_param,
+    affinity: Option<libc::cpu_set_t>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for RealtimeGuard {
+    fn drop(&mut self) {
+        // SAFETY: restores values read from this same thread in `Realtime::enter`.
+        unsafe {
+            if let Some(set) = &self.affinity {
+                libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), set);
+            }
+            libc::sched_setscheduler(0, self.policy, &self.param);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads `low` lows, then `high` highs, then low for good.
+    fn pulse(low: usize, high: usize) -> impl FnMut() -> Level {
+        let mut reads = 0;
+        move || {
+            reads += 1;
+            if reads > low && reads <= low + high {
+                Level::High
+            } else {
+                Level::Low
+            }
+        }
+    }
+
+    #[test]
+    fn wait_for_level_returns_once_the_level_shows() {
+        let mut read = pulse(3, 1);
+        assert!(wait_for_level(&mut read, Level::High, Duration::from_secs(1)).is_some());
+        assert!(wait_for_level(&mut read, Level::Low, Duration::from_secs(1)).is_some());
+        assert!(wait_for_level(|| Level::High, Level::High, Duration::ZERO).is_some());
+    }
+
+    #[test]
+    fn wait_for_level_times_out() {
+        let timeout = Duration::from_millis(2);
+        let start = Instant::now();
+        assert_eq!(wait_for_level(|| Level::Low, Level::High, timeout), None);
+        assert!(start.elapsed() > timeout);
+    }
+
+    #[test]
+    fn pulse_width_needs_both_edges() {
+        let timeout = Duration::from_millis(2);
+        assert!(pulse_width(pulse(5, 5), Level::High, timeout).is_some());
+        // A low pulse on a line that idles high.
+        let mut low = pulse(5, 5);
+        let idle_high = move || !low();
+        assert!(pulse_width(idle_high, Level::Low, timeout).is_some());
+        // Never starts, or never ends.
+        assert_eq!(pulse_width(|| Level::Low, Level::High, timeout), None);
+        assert_eq!(pulse_width(|| Level::High, Level::High, timeout), None);
+    }
+
+    #[test]
+    fn realtime_rejects_a_cpu_beyond_the_set() {
+        let realtime = Realtime {
+            cpu: Some(libc::CPU_SETSIZE as usize),
+            ..Realtime::default()
+        };
+        let error = realtime.enter().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,278 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+
+// Three SPI bits per LED bit at 2.4 MHz make each one 1.25 µs: `100` is a 0 with
+// 417 ns high, `110` a 1 with 833 ns high, both within the datasheet's ±150 ns.
+const SPI_CLOCK: u32 = 2_400_000;
+const ZERO: u32 = 0b100;
+const ONE: u32 = 0b110;
+// The strip latches after the line stays low for 280 µs on newer WS2812Bs (50 µs
+// on the old ones); 90 bytes at 2.4 MHz is 300 µs.
+const RESET_BYTES: usize = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    // Fully saturated colour at `hue` degrees: 0 red, 120 green, 240 blue.
+    pub fn from_hue(hue: f64) -> Self {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let x = 1.0 - (h % 2.0 - 1.0).abs();
+        let (r, g, b) = match h as u8 {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        let level = |v: f64| (v * 255.0).round() as u8;
+        Rgb::new(level(r), level(g), level(b))
+    }
+
+    // `factor` of the way from black to this colour.
+    pub fn scale(self, factor: f64) -> Self {
+        let factor = factor.clamp(0.0, 1.0);
+        let level = |v: u8| (v as f64 * factor).round() as u8;
+        Rgb::new(level(self.r), level(self.g), level(self.b))
+    }
+}
+
+// Maps each 8-bit level to what is sent, with gamma correction and the global
+// brightness applied. A gamma of 1.0 is linear; around 2.8 makes steps look even.
+pub fn levels(brightness: f64, gamma: f64) -> [u8; 256] {
+    let brightness = brightness.clamp(0.0, 1.0);
+    let mut table = [0u8; 256];
+    for (i, level) in table.iter_mut().enumerate() {
+        let value = (i as f64 / 255.0).powf(gamma) * brightness;
+        *level = (value * 255.0).round() as u8;
+    }
+    table
+}
+
+// The three SPI bytes for one LED byte, most significant bit first.
+pub fn encode_byte(byte: u8) -> [u8; 3] {
+    let mut bits = 0u32;
+    for i in (0..8).rev() {
+        bits = bits << 3 | if byte >> i & 1 == 1 { ONE } else { ZERO };
+    }
+    let [_, high, mid, low] = bits.to_be_bytes();
+    [high, mid, low]
+}
+
+// The whole SPI frame for `pixels`: green, red, blue for each LED in strip order,
+// then the reset time.
+pub fn encode(pixels: &[Rgb], levels: &[u8; 256]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(pixels.len() * 9 + RESET_BYTES);
+    for pixel in pixels {
+        for byte in [pixel.g, pixel.r, pixel.b] {
+            frame.extend_from_slice(&encode_byte(levels[byte as usize]));
+        }
+    }
+    frame.resize(frame.len() + RESET_BYTES, 0);
+    frame
+}
+
+// Colours around the colour wheel along the strip, turned by `frame` degrees.
+pub fn rainbow(pixels: &mut [Rgb], frame: u64) {
+    let count = pixels.len().max(1) as f64;
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = Rgb::from_hue(i as f64 * 360.0 / count + frame as f64);
+    }
+}
+
+// One lit pixel with a fading tail of `tail` pixels, moving a pixel per frame.
+pub fn chase(pixels: &mut [Rgb], frame: u64, color: Rgb, tail: usize) {
+    let len = pixels.len();
+    if len == 0 {
+        return;
+    }
+    pixels.fill(Rgb::BLACK);
+    let head = (frame % len as u64) as usize;
+    for i in 0..=tail.min(len - 1) {
+        let fade = 1.0 - i as f64 / (tail + 1) as f64;
+        pixels[(head + len - i) % len] = color.scale(fade);
+    }
+}
+
+// DIN to MOSI (GPIO10), through a level shifter if the strip runs on 5 V. SPI's
+// clock follows the core clock on the Pi 3 and 4, so fix it with `core_freq=250` in
+// config.txt. spidev sends at most 4096 bytes at once by default, about 440 pixels;
+// raise `spidev.bufsiz` on the kernel command line for longer strips.
+pub struct Ws2812 {
+    spi: Spi,
+    pixels: Vec<Rgb>,
+    brightness: f64,
+    gamma: f64,
+    levels: [u8; 256],
+}
+
+impl Ws2812 {
+    pub fn new(count: usize) -> Result<Self, rppal::spi::Error> {
+        // Only MOSI is used; the chip select goes unconnected.
+        let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, SPI_CLOCK, Mode::Mode0)?;
+        Ok(Ws2812 {
+            spi,
+            pixels: vec![Rgb::BLACK; count],
+            brightness: 1.0,
+            gamma: 1.0,
+            levels: levels(1.0, 1.0),
+        })
+    }
+
+    // Scales every pixel; a full strip at 1.0 draws 60 mA per LED.
+    pub fn with_brightness(mut self, brightness: f64) -> Self {
+        self.set_brightness(brightness);
+        self
+    }
+
+    pub fn with_gamma(mut self, gamma: f64) -> Self {
+        self.gamma = gamma;
+        self.levels = levels(self.brightness, gamma);
+        self
+    }
+
+    pub fn set_brightness(&mut self, brightness: f64) {
+        self.brightness = brightness.clamp(0.0, 1.0);
+        self.levels = levels(self.brightness, self.gamma);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    // Changes take effect on the next `show`.
+    pub fn pixels_mut(&mut self) -> &mut [Rgb] {
+        &mut self.pixels
+    }
+
+    pub fn set(&mut self, index: usize, color: Rgb) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = color;
+        }
+    }
+
+    pub fn fill(&mut self, color: Rgb) {
+        self.pixels.fill(color);
+    }
+
+    pub fn clear(&mut self) {
+        self.fill(Rgb::BLACK);
+    }
+
+    pub fn show(&mut self) -> Result<(), rppal::spi::Error> {
+        self.spi.write(&encode(&self.pixels, &self.levels))?;
+        Ok(())
+    }
+
+    // Calls `frame` with the pixels and the frame number every `period` and shows
+    // the result, until `running` is cleared. The strip is left dark.
+    pub fn animate<F>(
+        &mut self,
+        running: &AtomicBool,
+        period: Duration,
+        mut frame: F,
+    ) -> Result<(), rppal::spi::Error>
+    where
+        F: FnMut(&mut [Rgb], u64),
+    {
+        let mut next = Instant::now();
+        let mut number = 0;
+        while running.load(Ordering::SeqCst) {
+            frame(&mut self.pixels, number);
+            self.show()?;
+            number += 1;
+            // Drop frames rather than hurry to catch up after falling behind.
+            next = (next + period).max(Instant::now());
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+        self.clear();
+        self.show()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_bytes() {
+        assert_eq!(encode_byte(0x00), [0x92, 0x49, 0x24]);
+        assert_eq!(encode_byte(0xff), [0xdb, 0x6d, 0xb6]);
+        // 1000 0001
+        assert_eq!(encode_byte(0x81), [0xd2, 0x49, 0x26]);
+    }
+
+    #[test]
+    fn encodes_grb_then_reset() {
+        let linear = levels(1.0, 1.0);
+        let pixels = [Rgb::new(0x11, 0x22, 0x33), Rgb::new(0xff, 0x00, 0x80)];
+        let frame = encode(&pixels, &linear);
+        assert_eq!(frame.len(), 2 * 9 + RESET_BYTES);
+        let expected: Vec<u8> = [0x22, 0x11, 0x33, 0x00, 0xff, 0x80]
+            .iter()
+            .flat_map(|&byte| encode_byte(byte))
+            .collect();
+        assert_eq!(frame[..18], expected[..]);
+        assert!(frame[18..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(encode(&[], &linear), vec![0; RESET_BYTES]);
+    }
+
+    #[test]
+    fn level_table() {
+        let linear = levels(1.0, 1.0);
+        assert!(linear
+            .iter()
+            .enumerate()
+            .all(|(i, &level)| level as usize == i));
+
+        let dimmed = levels(0.5, 2.8);
+        assert_eq!(dimmed[0], 0);
+        assert_eq!(dimmed[255], 128);
+        assert!(dimmed.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Gamma pulls the middle down.
+        assert!(levels(1.0, 2.8)[128] < 64);
+
+        assert_eq!(levels(0.0, 2.8), [0; 256]);
+        assert_eq!(levels(2.0, 1.0)[255], 255);
+    }
+
+    #[test]
+    fn chase_wraps() {
+        let red = Rgb::new(255, 0, 0);
+        let mut pixels = [Rgb::BLACK; 3];
+        chase(&mut pixels, 4, red, 1);
+        assert_eq!(pixels, [red.scale(0.5), red, Rgb::BLACK]);
+
+        // The tail is cut to the strip rather than lapping the head.
+        chase(&mut pixels, 0, red, 10);
+        assert_eq!(pixels[0], red);
+        assert!(pixels[1] != Rgb::BLACK && pixels[2] != Rgb::BLACK);
+        assert!(pixels[2].r > pixels[1].r);
+
+        let mut one = [Rgb::BLACK];
+        chase(&mut one, 7, red, 3);
+        assert_eq!(one, [red]);
+
+        chase(&mut [], 3, red, 2);
+    }
+}